@@ -0,0 +1,194 @@
+use flate2::write::GzEncoder;
+use std::io::{self, Cursor, Write};
+use tracing::error;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{ContentType, Header},
+    Request, Response,
+};
+
+/// Bodies smaller than this gain too little to be worth compressing.
+static MIN_BYTES: usize = 1024;
+static BROTLI_QUALITY: u32 = 5;
+static BROTLI_WINDOW: u32 = 22;
+static ZSTD_LEVEL: i32 = 3;
+
+/// Content codings the server can produce, in order of preference.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+    fn token(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Picks the coding with the highest quality value in an `Accept-Encoding` header,
+    /// preferring our own order on ties. `None` if the client accepts none of them.
+    pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+        let codings: Vec<(String, f32)> = accept_encoding
+            .split(',')
+            .filter_map(|coding| {
+                let mut params = coding.split(';');
+                let name = params.next()?.trim().to_ascii_lowercase();
+                if name.is_empty() {
+                    return None;
+                }
+                // Malformed quality values are read as 0, i.e. not acceptable
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .map(|q| q.trim().parse().unwrap_or(0.0))
+                    .next()
+                    .unwrap_or(1.0);
+                Some((name, quality))
+            })
+            .collect();
+        let quality = |name: &str| {
+            codings
+                .iter()
+                .find(|(coding, _)| coding == name)
+                .or_else(|| codings.iter().find(|(coding, _)| coding == "*"))
+                .map_or(0.0, |(_, quality)| *quality)
+        };
+
+        Encoding::ALL
+            .into_iter()
+            .map(|encoding| (encoding, quality(encoding.token())))
+            .filter(|(_, quality)| *quality > 0.0)
+            // `max_by` keeps the last of equal elements
+            .rev()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(encoding, _)| encoding)
+    }
+
+    pub fn encode(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut writer =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                writer.write_all(body)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Zstd => zstd::encode_all(body, ZSTD_LEVEL),
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Compresses JSON responses with the best coding the client accepts.
+pub struct Compression;
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Compress JSON responses",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.content_type() != Some(ContentType::JSON) {
+            return;
+        }
+        // Caches must not hand a compressed body to clients that didn't ask for it
+        response.adjoin_header(Header::new("Vary", "Accept-Encoding"));
+
+        if response.headers().contains("Content-Encoding") {
+            return;
+        }
+        let Some(encoding) = request
+            .headers()
+            .get_one("Accept-Encoding")
+            .and_then(Encoding::negotiate)
+        else {
+            return;
+        };
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(err) => {
+                error!(error = %err, "could not read response body");
+                return;
+            }
+        };
+        if body.len() < MIN_BYTES {
+            response.set_sized_body(body.len(), Cursor::new(body));
+            return;
+        }
+
+        let encoded = match encoding.encode(&body) {
+            Ok(encoded) if encoded.len() < body.len() => {
+                response.set_header(Header::new("Content-Encoding", encoding.token()));
+                encoded
+            }
+            Ok(_) => body,
+            Err(err) => {
+                error!(encoding = encoding.token(), error = %err, "could not compress response");
+                body
+            }
+        };
+        response.set_sized_body(encoded.len(), Cursor::new(encoded));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn negotiate_follows_quality_values() {
+        assert_eq!(
+            Encoding::negotiate("gzip, deflate, br, zstd"),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(Encoding::negotiate("gzip, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(
+            Encoding::negotiate("ZSTD;q=0.8, gzip;q=0.8"),
+            Some(Encoding::Zstd)
+        );
+        assert_eq!(Encoding::negotiate("*"), Some(Encoding::Brotli));
+        assert_eq!(Encoding::negotiate("br;q=0, *;q=0.1"), Some(Encoding::Zstd));
+        assert_eq!(Encoding::negotiate("gzip;q=0"), None);
+        assert_eq!(Encoding::negotiate("deflate, identity"), None);
+        assert_eq!(Encoding::negotiate(""), None);
+    }
+
+    #[test]
+    fn encodings_round_trip() {
+        let body = br#"{"pkg_name":"foo","pkg_version":"1.0.0"}"#.repeat(100);
+
+        let mut decoded = Vec::new();
+        let encoded = Encoding::Brotli.encode(&body).unwrap();
+        brotli::Decompressor::new(&encoded[..], 4096)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+
+        let encoded = Encoding::Zstd.encode(&body).unwrap();
+        assert_eq!(zstd::decode_all(&encoded[..]).unwrap(), body);
+
+        let mut decoded = Vec::new();
+        let encoded = Encoding::Gzip.encode(&body).unwrap();
+        GzDecoder::new(&encoded[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+        assert!(encoded.len() < body.len());
+    }
+}
@@ -0,0 +1,149 @@
+use chrono::{DateTime, SubsecRound, Utc};
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+
+use rocket::{
+    http::{Header, Status},
+    request::{FromRequest, Outcome},
+    response::{self, Responder},
+    serde::{json, Serialize},
+    Request, Response,
+};
+
+static HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Weak validator of `value`'s JSON form. Weak because responses may carry fields
+/// that change without the value changing, e.g. `age_seconds`.
+pub fn etag<T: Serialize>(value: &T) -> String {
+    let body = json::to_string(value).expect("responses serialize to JSON");
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("W/\"{}\"", hex)
+}
+
+/// The `If-None-Match` and `If-Modified-Since` headers of a request.
+#[derive(Debug, Default)]
+pub struct Preconditions {
+    if_none_match: Option<String>,
+    if_modified_since: Option<DateTime<Utc>>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Preconditions {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Preconditions, Infallible> {
+        let headers = request.headers();
+        Outcome::Success(Preconditions {
+            if_none_match: headers.get_one("If-None-Match").map(str::to_owned),
+            // Unparsable dates are ignored, as RFC 9110 asks
+            if_modified_since: headers
+                .get_one("If-Modified-Since")
+                .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+                .map(|date| date.with_timezone(&Utc)),
+        })
+    }
+}
+
+impl Preconditions {
+    /// Whether the client's copy is current. `If-Modified-Since` only counts without
+    /// `If-None-Match` and has whole seconds, and entity tags compare weakly.
+    pub fn not_modified(&self, etag: &str, last_modified: DateTime<Utc>) -> bool {
+        match (&self.if_none_match, self.if_modified_since) {
+            (Some(tags), _) => {
+                let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+                tags.split(',')
+                    .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+            }
+            (None, Some(since)) => last_modified.trunc_subsecs(0) <= since,
+            (None, None) => false,
+        }
+    }
+}
+
+/// A response with validators and caching headers, reduced to `304 Not Modified` if the
+/// client's copy is current.
+pub struct Cached<R> {
+    body: Option<R>,
+    etag: String,
+    last_modified: DateTime<Utc>,
+    /// Seconds the response stays fresh, `None` if unknown
+    max_age: Option<i64>,
+}
+
+impl<R> Cached<R> {
+    pub fn new(
+        body: R,
+        etag: String,
+        last_modified: DateTime<Utc>,
+        max_age: Option<i64>,
+        preconditions: &Preconditions,
+    ) -> Cached<R> {
+        let not_modified = preconditions.not_modified(&etag, last_modified);
+        Cached {
+            body: (!not_modified).then_some(body),
+            etag,
+            last_modified,
+            max_age,
+        }
+    }
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Cached<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = match self.body {
+            Some(body) => body.respond_to(request)?,
+            None => Response::build().status(Status::NotModified).finalize(),
+        };
+
+        let cache_control = match self.max_age {
+            Some(max_age) => format!("public, max-age={}", max_age.max(0)),
+            None => "no-cache".to_owned(),
+        };
+        response.set_header(Header::new("ETag", self.etag));
+        response.set_header(Header::new(
+            "Last-Modified",
+            self.last_modified.format(HTTP_DATE).to_string(),
+        ));
+        response.set_header(Header::new("Cache-Control", cache_control));
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn etag_follows_contents() {
+        let tag = etag(&vec!["foo"]);
+        assert!(tag.starts_with("W/\""));
+        assert_eq!(tag, etag(&vec!["foo"]));
+        assert_ne!(tag, etag(&vec!["bar"]));
+    }
+
+    #[test]
+    fn not_modified_checks_validators() {
+        let modified = Utc.with_ymd_and_hms(2022, 10, 25, 12, 0, 0).unwrap();
+        let etag = "W/\"abc\"";
+        let preconditions =
+            |if_none_match: Option<&str>, since: Option<DateTime<Utc>>| Preconditions {
+                if_none_match: if_none_match.map(str::to_owned),
+                if_modified_since: since,
+            };
+
+        assert!(!preconditions(None, None).not_modified(etag, modified));
+        assert!(preconditions(Some("\"abc\""), None).not_modified(etag, modified));
+        assert!(preconditions(Some("\"xyz\", W/\"abc\""), None).not_modified(etag, modified));
+        assert!(preconditions(Some("*"), None).not_modified(etag, modified));
+        assert!(preconditions(None, Some(modified)).not_modified(etag, modified));
+        // `Last-Modified` drops the fraction of a second
+        let fraction = modified + chrono::Duration::milliseconds(250);
+        assert!(preconditions(None, Some(modified)).not_modified(etag, fraction));
+        let earlier = modified - chrono::Duration::seconds(1);
+        assert!(!preconditions(None, Some(earlier)).not_modified(etag, modified));
+        // A mismatching tag wins over a matching date
+        assert!(!preconditions(Some("\"xyz\""), Some(modified)).not_modified(etag, modified));
+    }
+}
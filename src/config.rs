@@ -0,0 +1,290 @@
+use clap::Parser;
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    time::Duration,
+};
+
+use crate::logging::LogFormat;
+
+use rocket::{
+    figment::{
+        providers::{Env, Format, Serialized, Toml},
+        Figment,
+    },
+    serde::{Deserialize, Serialize},
+};
+
+static DEFAULT_CONFIG_FILE: &str = "cransubs.toml";
+static ENV_PREFIX: &str = "CRANSUBS_";
+
+/// Server settings, layered from defaults, `cransubs.toml` (or the file given by
+/// `--config`/`CRANSUBS_CONFIG`), `CRANSUBS_*` environment variables and command line flags,
+/// each overriding the previous one.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Settings {
+    pub address: IpAddr,
+    pub port: u16,
+    pub database: PathBuf,
+    /// Days the submissions of past snapshots are kept in the database, 0 to keep them all
+    pub history_retention_days: u64,
+    pub ftp_host: String,
+    pub ftp_port: u16,
+    pub ftp_root: String,
+    pub ftp_user: String,
+    pub ftp_password: String,
+    pub max_depth: u32,
+    /// Seconds between two captures
+    pub refresh_interval: u64,
+    /// Upper bound of the random seconds added to each refresh interval
+    pub refresh_jitter: u64,
+    /// Seconds after which a running capture is abandoned. Reading descriptions afterwards
+    /// gets as long again.
+    pub capture_deadline: u64,
+    /// Read `DESCRIPTION` from the tarball of every package version not seen before
+    pub enrich_descriptions: bool,
+    /// URL or local path of the CRAN `PACKAGES` index, empty to not compare with CRAN
+    pub cran_index: String,
+    /// Seconds before the CRAN index is reloaded
+    pub cran_index_max_age: u64,
+    /// Bearer token required to manage webhooks, empty to disable the webhook API
+    pub webhook_token: String,
+    /// Allow webhooks to loopback, link-local and private network addresses
+    pub webhook_allow_private_addresses: bool,
+    /// Origins allowed to make cross-origin requests, `*` for any
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_allowed_methods: Vec<String>,
+    /// Request headers allowed in cross-origin requests
+    pub cors_allowed_headers: Vec<String>,
+    /// Allow cross-origin requests with cookies, requires explicit origins and headers
+    pub cors_allow_credentials: bool,
+    /// Seconds browsers may cache a preflight response
+    pub cors_max_age: u64,
+    pub log_format: LogFormat,
+    /// Log filter directives, e.g. `info,cransubs::snapshot=debug`
+    pub log_filter: String,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            address: Ipv4Addr::new(0, 0, 0, 0).into(),
+            port: 8080,
+            database: PathBuf::from("cransubs.sqlite"),
+            history_retention_days: 30,
+            ftp_host: "cran.r-project.org".to_owned(),
+            ftp_port: 21,
+            ftp_root: "/incoming".to_owned(),
+            ftp_user: "anonymous".to_owned(),
+            ftp_password: "anonymous".to_owned(),
+            max_depth: 2,
+            refresh_interval: 60 * 10,
+            refresh_jitter: 30,
+            capture_deadline: 60 * 5,
+            enrich_descriptions: false,
+            cran_index: "https://cran.r-project.org/src/contrib/PACKAGES".to_owned(),
+            cran_index_max_age: 60 * 60,
+            webhook_token: String::new(),
+            webhook_allow_private_addresses: false,
+            cors_allowed_origins: vec!["*".to_owned()],
+            cors_allowed_methods: ["GET", "POST", "DELETE"].map(str::to_owned).to_vec(),
+            cors_allowed_headers: ["Content-Type", "If-None-Match", "If-Modified-Since"]
+                .map(str::to_owned)
+                .to_vec(),
+            cors_allow_credentials: false,
+            cors_max_age: 60 * 60,
+            log_format: LogFormat::Text,
+            log_filter: "info,rocket=warn".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Parser, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[command(version, about = "CRAN submission tracker server")]
+struct Cli {
+    /// Configuration file [default: cransubs.toml]
+    #[arg(long, env = "CRANSUBS_CONFIG")]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+    /// Address to bind the HTTP server to
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<IpAddr>,
+    /// Port to bind the HTTP server to
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
+    /// SQLite database holding the snapshot history
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    database: Option<PathBuf>,
+    /// Days the submissions of past snapshots are kept in the database, 0 to keep them all
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    history_retention_days: Option<u64>,
+    /// FTP server to crawl
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ftp_host: Option<String>,
+    /// FTP server port
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ftp_port: Option<u16>,
+    /// Folder on the FTP server holding the submissions
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ftp_root: Option<String>,
+    /// How many folder levels below the root to crawl
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_depth: Option<u32>,
+    /// Seconds between two captures
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_interval: Option<u64>,
+    /// Upper bound of the random seconds added to each refresh interval
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_jitter: Option<u64>,
+    /// Seconds after which a running capture is abandoned. Reading descriptions afterwards
+    /// gets as long again.
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capture_deadline: Option<u64>,
+    /// Read `DESCRIPTION` from the tarball of every package version not seen before
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enrich_descriptions: Option<bool>,
+    /// URL or local path of the CRAN `PACKAGES` index, empty to not compare with CRAN
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cran_index: Option<String>,
+    /// Seconds before the CRAN index is reloaded
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cran_index_max_age: Option<u64>,
+    /// Bearer token required to manage webhooks, empty to disable the webhook API
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webhook_token: Option<String>,
+    /// Allow webhooks to loopback, link-local and private network addresses
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webhook_allow_private_addresses: Option<bool>,
+    /// Comma separated origins allowed to make cross-origin requests, `*` for any
+    #[arg(long, value_delimiter = ',')]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cors_allowed_origins: Option<Vec<String>>,
+    /// Comma separated methods allowed in cross-origin requests
+    #[arg(long, value_delimiter = ',')]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cors_allowed_methods: Option<Vec<String>>,
+    /// Comma separated request headers allowed in cross-origin requests
+    #[arg(long, value_delimiter = ',')]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cors_allowed_headers: Option<Vec<String>>,
+    /// Allow cross-origin requests with cookies, requires explicit origins and headers
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cors_allow_credentials: Option<bool>,
+    /// Seconds browsers may cache a preflight response
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cors_max_age: Option<u64>,
+    /// Log output format
+    #[arg(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_format: Option<LogFormat>,
+    /// Log filter directives, e.g. `info,cransubs::snapshot=debug`
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_filter: Option<String>,
+}
+
+impl Settings {
+    pub fn load() -> Result<Settings, String> {
+        let cli = Cli::parse();
+        let file = cli
+            .config
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE));
+
+        let settings: Settings = Figment::from(Serialized::defaults(Settings::default()))
+            .merge(Toml::file(file))
+            .merge(Env::prefixed(ENV_PREFIX).ignore(&["config"]))
+            .merge(Serialized::defaults(cli))
+            .extract()
+            .map_err(|err| err.to_string())?;
+
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.ftp_host.is_empty() {
+            return Err("ftp_host must not be empty".to_owned());
+        }
+        if !self.ftp_root.starts_with('/')
+            || (self.ftp_root.len() > 1 && self.ftp_root.ends_with('/'))
+        {
+            return Err(format!(
+                "ftp_root must be an absolute path without trailing slash, got '{}'",
+                self.ftp_root
+            ));
+        }
+        if self.refresh_interval == 0 {
+            return Err("refresh_interval must be at least one second".to_owned());
+        }
+        if self.capture_deadline == 0 {
+            return Err("capture_deadline must be at least one second".to_owned());
+        }
+        Ok(())
+    }
+
+    pub fn history_retention(&self) -> Option<Duration> {
+        (self.history_retention_days > 0)
+            .then(|| Duration::from_secs(self.history_retention_days.saturating_mul(24 * 60 * 60)))
+    }
+
+    pub fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.refresh_interval)
+    }
+
+    pub fn refresh_jitter(&self) -> Duration {
+        Duration::from_secs(self.refresh_jitter)
+    }
+
+    pub fn capture_deadline(&self) -> Duration {
+        Duration::from_secs(self.capture_deadline)
+    }
+
+    pub fn cran_index_max_age(&self) -> Duration {
+        Duration::from_secs(self.cran_index_max_age)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rejects_bad_settings() {
+        assert!(Settings::default().validate().is_ok());
+
+        for root in ["incoming", "/incoming/"] {
+            let settings = Settings {
+                ftp_root: root.to_owned(),
+                ..Settings::default()
+            };
+            assert!(settings.validate().is_err(), "accepted ftp_root '{}'", root);
+        }
+
+        let settings = Settings {
+            refresh_interval: 0,
+            ..Settings::default()
+        };
+        assert!(settings.validate().is_err());
+    }
+}
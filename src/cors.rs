@@ -0,0 +1,186 @@
+use std::str::FromStr;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{Header, Method, Status},
+    Request, Response,
+};
+
+use crate::config::Settings;
+
+static ANY: &str = "*";
+
+/// Cross-origin access rules, applied to every response.
+///
+/// Requests from other origins get the `Access-Control-*` headers only if their `Origin`
+/// is allowed, preflights additionally only if the requested method is.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<Method>,
+    allowed_headers: Vec<String>,
+    allow_credentials: bool,
+    max_age: u64,
+}
+
+impl Cors {
+    pub fn new(settings: &Settings) -> Result<Cors, String> {
+        let allowed_methods = settings
+            .cors_allowed_methods
+            .iter()
+            .map(|method| {
+                Method::from_str(method).map_err(|_| format!("Invalid CORS method '{}'", method))
+            })
+            .collect::<Result<_, _>>()?;
+        if settings.cors_allow_credentials
+            && (settings
+                .cors_allowed_origins
+                .iter()
+                .any(|origin| origin == ANY)
+                || settings
+                    .cors_allowed_headers
+                    .iter()
+                    .any(|header| header == ANY))
+        {
+            return Err(
+                "cors_allow_credentials requires explicit origins and headers, not '*'".to_owned(),
+            );
+        }
+
+        Ok(Cors {
+            allowed_origins: settings.cors_allowed_origins.clone(),
+            allowed_methods,
+            allowed_headers: settings.cors_allowed_headers.clone(),
+            allow_credentials: settings.cors_allow_credentials,
+            max_age: settings.cors_max_age,
+        })
+    }
+
+    fn any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == ANY)
+    }
+
+    /// The `Access-Control-Allow-Origin` value for `origin`, `None` if it isn't allowed.
+    fn allow_origin<'a>(&self, origin: &'a str) -> Option<&'a str> {
+        if self.any_origin() {
+            Some(ANY)
+        } else if self.allowed_origins.iter().any(|allowed| allowed == origin) {
+            Some(origin)
+        } else {
+            None
+        }
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        Method::from_str(method).is_ok_and(|method| self.allowed_methods.contains(&method))
+    }
+}
+
+/// Answers preflight requests for any path, the fairing adds the headers.
+#[options("/<_..>")]
+pub fn preflight() -> Status {
+    Status::NoContent
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "Add CORS headers to responses",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        // Responses differ by origin unless every origin gets the same
+        if !self.any_origin() {
+            response.adjoin_header(Header::new("Vary", "Origin"));
+        }
+
+        let Some(allow_origin) = request
+            .headers()
+            .get_one("Origin")
+            .and_then(|origin| self.allow_origin(origin))
+        else {
+            return;
+        };
+        let preflight_method = request.headers().get_one("Access-Control-Request-Method");
+        if request.method() == Method::Options {
+            match preflight_method {
+                Some(method) if self.allows_method(method) => {
+                    let methods: Vec<&str> = self
+                        .allowed_methods
+                        .iter()
+                        .map(|method| method.as_str())
+                        .collect();
+                    response.set_header(Header::new(
+                        "Access-Control-Allow-Methods",
+                        methods.join(", "),
+                    ));
+                    response.set_header(Header::new(
+                        "Access-Control-Allow-Headers",
+                        self.allowed_headers.join(", "),
+                    ));
+                    response.set_header(Header::new(
+                        "Access-Control-Max-Age",
+                        self.max_age.to_string(),
+                    ));
+                }
+                Some(_) => return,
+                None => {}
+            }
+        }
+
+        response.set_header(Header::new(
+            "Access-Control-Allow-Origin",
+            allow_origin.to_owned(),
+        ));
+        if self.allow_credentials {
+            response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors(origins: &[&str], credentials: bool) -> Result<Cors, String> {
+        Cors::new(&Settings {
+            cors_allowed_origins: origins.iter().map(|o| o.to_string()).collect(),
+            cors_allowed_headers: vec!["Content-Type".to_owned()],
+            cors_allow_credentials: credentials,
+            ..Settings::default()
+        })
+    }
+
+    #[test]
+    fn new_rejects_credentials_with_wildcards() {
+        assert!(Cors::new(&Settings::default()).is_ok());
+        assert!(cors(&["*"], true).is_err());
+        assert!(cors(&["https://example.org"], true).is_ok());
+
+        let settings = Settings {
+            cors_allowed_methods: vec!["FETCH".to_owned()],
+            ..Settings::default()
+        };
+        assert!(Cors::new(&settings).is_err());
+    }
+
+    #[test]
+    fn allow_origin_matches_configured_origins() {
+        let any = cors(&["*"], false).unwrap();
+        assert_eq!(any.allow_origin("https://example.org"), Some("*"));
+
+        let listed = cors(&["https://example.org"], true).unwrap();
+        assert_eq!(
+            listed.allow_origin("https://example.org"),
+            Some("https://example.org")
+        );
+        assert_eq!(listed.allow_origin("https://example.org.evil"), None);
+        assert_eq!(listed.allow_origin("null"), None);
+
+        assert!(listed.allows_method("GET"));
+        assert!(!listed.allows_method("PUT"));
+    }
+}
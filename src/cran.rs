@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+
+use rocket::{
+    serde::{Deserialize, Serialize},
+    tokio::{fs, sync::Mutex},
+};
+
+use crate::description::parse_dcf;
+use crate::snapshot::Snapshot;
+use crate::version::PackageVersion;
+
+static REQUEST_TIMEOUT_SECONDS: u64 = 60;
+
+/// Whether a submission is a package's first release on CRAN.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Release {
+    New,
+    Update,
+}
+
+/// Published version of every package, from a CRAN `PACKAGES` file.
+#[derive(Clone, Debug, Default)]
+pub struct CranIndex {
+    versions: HashMap<String, String>,
+}
+
+impl CranIndex {
+    pub fn parse(packages: &str) -> CranIndex {
+        let versions = parse_dcf(packages)
+            .into_iter()
+            .filter_map(|mut record| Some((record.remove("Package")?, record.remove("Version")?)))
+            .collect();
+        CranIndex { versions }
+    }
+
+    pub fn published(&self, pkg_name: &str) -> Option<&str> {
+        self.versions.get(pkg_name).map(String::as_str)
+    }
+}
+
+/// Loads the index from an `http(s)://` URL or a local file and keeps it for `max_age`.
+pub struct CranIndexLoader {
+    source: String,
+    max_age: Duration,
+    client: reqwest::Client,
+    cached: Mutex<Option<(Instant, Arc<CranIndex>)>>,
+}
+
+impl CranIndexLoader {
+    pub fn new(source: &str, max_age: Duration) -> CranIndexLoader {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECONDS))
+            .user_agent(concat!("cransubs/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("HTTP client configuration is valid");
+
+        CranIndexLoader {
+            source: source.to_owned(),
+            max_age,
+            client,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// The cached index, reloaded once it's older than `max_age`. If reloading fails the
+    /// previous index is kept. `None` if no source is configured or none could be loaded yet.
+    pub async fn index(&self) -> Option<Arc<CranIndex>> {
+        if self.source.is_empty() {
+            return None;
+        }
+
+        let mut cached = self.cached.lock().await;
+        if let Some((loaded, index)) = cached.as_ref() {
+            if loaded.elapsed() < self.max_age {
+                return Some(index.clone());
+            }
+        }
+
+        match self.load().await {
+            Ok(index) => {
+                info!(source = %self.source, packages = index.versions.len(), "loaded CRAN index");
+                let index = Arc::new(index);
+                *cached = Some((Instant::now(), index.clone()));
+                Some(index)
+            }
+            Err(err) => {
+                error!(source = %self.source, error = %err, "could not load CRAN index");
+                cached.as_ref().map(|(_, index)| index.clone())
+            }
+        }
+    }
+
+    async fn load(&self) -> Result<CranIndex, String> {
+        let packages = if self.source.starts_with("http://") || self.source.starts_with("https://")
+        {
+            self.client
+                .get(&self.source)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|err| err.to_string())?
+                .text()
+                .await
+                .map_err(|err| err.to_string())?
+        } else {
+            fs::read_to_string(&self.source)
+                .await
+                .map_err(|err| err.to_string())?
+        };
+
+        Ok(CranIndex::parse(&packages))
+    }
+}
+
+/// Compares every submission with the version published on CRAN.
+pub fn annotate(snap: &mut Snapshot, index: &CranIndex) {
+    for sub in &mut snap.submissions {
+        let published = index.published(&sub.pkg_name);
+        let version = sub.pkg_version.parse::<PackageVersion>().ok();
+
+        sub.cran_version = published.map(str::to_owned);
+        sub.release = Some(match published {
+            Some(_) => Release::Update,
+            None => Release::New,
+        });
+        sub.version_delta = version
+            .as_ref()
+            .zip(published.and_then(|published| published.parse().ok()))
+            .map(|(version, published)| version.delta(&published));
+        sub.version_is_newer_than_cran = version
+            .zip(published)
+            .and_then(|(version, published)| version.is_newer_than(published));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Submission;
+    use crate::version::VersionDelta;
+
+    static PACKAGES: &str = "tests/fixtures/PACKAGES";
+
+    #[rocket::async_test]
+    async fn loader_reads_local_index() {
+        let loader = CranIndexLoader::new(PACKAGES, Duration::from_secs(60));
+        let index = loader.index().await.unwrap();
+        assert_eq!(index.published("foo"), Some("1.9.3"));
+        assert_eq!(index.published("baz"), Some("2.1"));
+        assert_eq!(index.published("bar"), None);
+
+        let disabled = CranIndexLoader::new("", Duration::from_secs(60));
+        assert!(disabled.index().await.is_none());
+    }
+
+    #[test]
+    fn annotate_compares_with_cran() {
+        let index = CranIndex::parse(&std::fs::read_to_string(PACKAGES).unwrap());
+        let mut snap = Snapshot::new();
+        snap.submissions = [("foo", "1.10.0"), ("bar", "0.1"), ("baz", "2.1")]
+            .into_iter()
+            .map(|(pkg_name, pkg_version)| {
+                Submission::new("pretest", pkg_name, pkg_version, snap.capture_time)
+            })
+            .collect();
+
+        annotate(&mut snap, &index);
+
+        let annotations: Vec<_> = snap
+            .submissions
+            .iter()
+            .map(|sub| {
+                (
+                    sub.cran_version.as_deref(),
+                    sub.release,
+                    sub.version_delta,
+                    sub.version_is_newer_than_cran,
+                )
+            })
+            .collect();
+        assert_eq!(
+            annotations,
+            vec![
+                (
+                    Some("1.9.3"),
+                    Some(Release::Update),
+                    Some(VersionDelta::Minor),
+                    Some(true)
+                ),
+                (None, Some(Release::New), None, None),
+                (
+                    Some("2.1"),
+                    Some(Release::Update),
+                    Some(VersionDelta::Same),
+                    Some(false)
+                ),
+            ]
+        );
+    }
+}
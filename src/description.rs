@@ -0,0 +1,382 @@
+use flate2::read::GzDecoder;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tar::Archive;
+use tracing::{debug, error, info_span, warn};
+
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::history::History;
+use crate::lifecycle::PackageKey;
+use crate::snapshot::{CaptureError, Snapshot, Submission};
+use crate::source::SubmissionSource;
+
+/// Compressed bytes read from a tarball before giving up on finding `DESCRIPTION`.
+static MAX_TARBALL_BYTES: u64 = 10 * 1024 * 1024;
+
+lazy_static! {
+    // `pkg (>= 1.0)`, the version requirement being optional
+    static ref RE_DEPENDENCY: Regex =
+        Regex::new(r"^([^\s(]+)\s*(?:\(\s*([^)]*?)\s*\))?$").unwrap();
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Dependency {
+    pub package: String,
+    /// Version requirement, e.g. `>= 1.0`
+    pub version: Option<String>,
+}
+
+/// Metadata from a package's `DESCRIPTION` file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Description {
+    pub title: Option<String>,
+    pub maintainer: Option<String>,
+    pub license: Option<String>,
+    /// Required R version from `Depends`, e.g. `>= 4.1.0`
+    pub r_version: Option<String>,
+    pub depends: Vec<Dependency>,
+    pub imports: Vec<Dependency>,
+    pub linking_to: Vec<Dependency>,
+    pub suggests: Vec<Dependency>,
+}
+
+/// Parses the Debian control file format R uses for `DESCRIPTION` and `PACKAGES`. Records
+/// are separated by blank lines and indented lines continue the previous field.
+pub fn parse_dcf(dcf: &str) -> Vec<HashMap<&str, String>> {
+    let mut records = Vec::new();
+    let mut fields: HashMap<&str, String> = HashMap::new();
+    let mut current: Option<&str> = None;
+
+    for line in dcf.lines() {
+        if line.trim().is_empty() {
+            if !fields.is_empty() {
+                records.push(std::mem::take(&mut fields));
+            }
+            current = None;
+        } else if line.starts_with([' ', '\t']) {
+            if let Some(value) = current.and_then(|field| fields.get_mut(field)) {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((field, value)) = line.split_once(':') {
+            fields.insert(field, value.trim().to_owned());
+            current = Some(field);
+        } else {
+            current = None;
+        }
+    }
+    if !fields.is_empty() {
+        records.push(fields);
+    }
+
+    records
+}
+
+impl Description {
+    pub fn parse(dcf: &str) -> Description {
+        let fields = parse_dcf(dcf).into_iter().next().unwrap_or_default();
+
+        let text = |field: &str| {
+            fields
+                .get(field)
+                .map(|value| value.split_whitespace().collect::<Vec<_>>().join(" "))
+                .filter(|value| !value.is_empty())
+        };
+        let dependencies =
+            |field: &str| text(field).map_or_else(Vec::new, |v| parse_dependencies(&v));
+
+        let (r, depends): (Vec<_>, Vec<_>) = dependencies("Depends")
+            .into_iter()
+            .partition(|dep| dep.package == "R");
+
+        Description {
+            title: text("Title"),
+            maintainer: text("Maintainer"),
+            license: text("License"),
+            r_version: r.into_iter().find_map(|dep| dep.version),
+            depends,
+            imports: dependencies("Imports"),
+            linking_to: dependencies("LinkingTo"),
+            suggests: dependencies("Suggests"),
+        }
+    }
+}
+
+fn parse_dependencies(field: &str) -> Vec<Dependency> {
+    field
+        .split(',')
+        .filter_map(|dep| RE_DEPENDENCY.captures(dep.trim()))
+        .map(|caps| Dependency {
+            package: caps[1].to_owned(),
+            version: caps.get(2).map(|v| v.as_str().to_owned()),
+        })
+        .collect()
+}
+
+/// Counts the bytes read through it.
+struct Counted<R> {
+    inner: R,
+    bytes: u64,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+fn find_entry<R: Read>(archive: &mut Archive<R>, path: &str) -> io::Result<Option<String>> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.to_str() == Some(path) {
+            let mut bytes = Vec::new();
+            entry.read_to_end(&mut bytes)?;
+            // Older packages may declare a latin1 `Encoding`
+            return Ok(Some(String::from_utf8_lossy(&bytes).into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads `<pkg_name>/DESCRIPTION` from a `.tar.gz` of `file_bytes` bytes, consuming `tarball`
+/// only up to that entry. A tarball that is malformed, or too large to reach the entry, has
+/// none. One that ends before `file_bytes` was cut short and gives an `UnexpectedEof` error.
+pub fn extract<R: Read>(tarball: R, pkg_name: &str, file_bytes: u64) -> io::Result<Option<String>> {
+    let path = format!("{}/DESCRIPTION", pkg_name);
+    let mut counted = Counted {
+        inner: tarball.take(MAX_TARBALL_BYTES),
+        bytes: 0,
+    };
+    let found = find_entry(&mut Archive::new(GzDecoder::new(&mut counted)), &path);
+
+    match found {
+        Err(err)
+            if err.kind() == io::ErrorKind::UnexpectedEof
+                && counted.bytes < file_bytes.min(MAX_TARBALL_BYTES) =>
+        {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "transfer ended after {} of {} bytes",
+                    counted.bytes, file_bytes
+                ),
+            ))
+        }
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::InvalidData
+                    | io::ErrorKind::InvalidInput
+                    | io::ErrorKind::Other
+            ) =>
+        {
+            debug!(pkg_name, error = %err, "no readable DESCRIPTION");
+            Ok(None)
+        }
+        res => res,
+    }
+}
+
+/// Attaches `DESCRIPTION` metadata to the submissions, fetching the tarball of each package
+/// version only the first time it is seen. `connect` is only called if a version is missing
+/// from the cache. Failed downloads aren't cached and are retried by the next capture.
+pub fn enrich<S: SubmissionSource>(
+    snap: &mut Snapshot,
+    connect: impl FnOnce() -> Result<S, CaptureError>,
+    root: &str,
+    history: &History,
+    cancel: &AtomicBool,
+) -> Result<(), CaptureError> {
+    let _span = info_span!("enrich").entered();
+
+    let packages: Vec<PackageKey> = snap.submissions.iter().map(Submission::package).collect();
+    let mut known = history.descriptions(&packages).unwrap_or_else(|err| {
+        error!(error = %err, "could not load cached descriptions");
+        HashMap::new()
+    });
+    let mut source = if packages.iter().any(|package| !known.contains_key(package)) {
+        Some(connect()?)
+    } else {
+        None
+    };
+
+    for (sub, package) in snap.submissions.iter_mut().zip(packages) {
+        let fetch = !known.contains_key(&package) && !cancel.load(Ordering::Relaxed);
+        if let Some(source) = source.as_mut().filter(|_| fetch) {
+            let file_path = [
+                root,
+                &sub.folder,
+                &format!("{}_{}.tar.gz", sub.pkg_name, sub.pkg_version),
+            ]
+            .iter()
+            .filter(|part| !part.is_empty())
+            .copied()
+            .collect::<Vec<_>>()
+            .join("/");
+
+            match source.description(&file_path, &sub.pkg_name, sub.file_bytes) {
+                Ok(dcf) => {
+                    let description = dcf.as_deref().map(Description::parse);
+                    if let Err(err) = history.store_description(&package, description.as_ref()) {
+                        error!(error = %err, "could not cache description");
+                    }
+                    known.insert(package.clone(), description);
+                }
+                Err(err) => warn!(path = %file_path, error = %err, "could not fetch DESCRIPTION"),
+            }
+        }
+        sub.description = known.get(&package).cloned().flatten();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FixtureSource;
+    use flate2::{write::GzEncoder, Compression};
+
+    static DESCRIPTION: &str = "Package: foo
+Title: Frobnicate
+    Everything
+Version: 1.0.0
+Maintainer: Jane Doe <jane@example.org>
+Depends: R (>= 4.1.0), methods
+Imports: bar (>=0.2-1),
+    baz
+License: MIT + file LICENSE
+";
+
+    fn tarball(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn parse_reads_fields() {
+        let description = Description::parse(DESCRIPTION);
+        assert_eq!(description.title.as_deref(), Some("Frobnicate Everything"));
+        assert_eq!(
+            description.maintainer.as_deref(),
+            Some("Jane Doe <jane@example.org>")
+        );
+        assert_eq!(description.license.as_deref(), Some("MIT + file LICENSE"));
+        assert_eq!(description.r_version.as_deref(), Some(">= 4.1.0"));
+        assert_eq!(
+            description.depends,
+            vec![Dependency {
+                package: "methods".to_owned(),
+                version: None
+            }]
+        );
+        assert_eq!(
+            description.imports,
+            vec![
+                Dependency {
+                    package: "bar".to_owned(),
+                    version: Some(">=0.2-1".to_owned())
+                },
+                Dependency {
+                    package: "baz".to_owned(),
+                    version: None
+                },
+            ]
+        );
+        assert!(description.suggests.is_empty());
+    }
+
+    #[test]
+    fn extract_finds_description() {
+        let tarball = tarball(&[
+            ("foo/R/foo.R", "foo <- 1"),
+            ("foo/DESCRIPTION", DESCRIPTION),
+        ]);
+        let size = tarball.len() as u64;
+        assert_eq!(
+            extract(&tarball[..], "foo", size).unwrap().as_deref(),
+            Some(DESCRIPTION)
+        );
+        assert_eq!(extract(&tarball[..], "bar", size).unwrap(), None);
+        assert_eq!(extract(&b"not a tarball"[..], "foo", 13).unwrap(), None);
+    }
+
+    #[test]
+    fn extract_fails_on_truncated_transfers() {
+        let tarball = tarball(&[
+            ("foo/R/foo.R", "foo <- 1"),
+            ("foo/DESCRIPTION", DESCRIPTION),
+        ]);
+        let partial = &tarball[..tarball.len() / 2];
+
+        // Cut short by the network, worth retrying
+        let err = extract(partial, "foo", tarball.len() as u64).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        // The file itself is truncated
+        assert_eq!(extract(partial, "foo", partial.len() as u64).unwrap(), None);
+    }
+
+    #[test]
+    fn enrich_fetches_each_version_once() {
+        let history = History::open(":memory:").unwrap();
+        let mut snap = Snapshot::new();
+        snap.submissions = [("waiting", "baz", "2.1"), ("pretest", "foo", "1.0.0")]
+            .into_iter()
+            .map(|(folder, pkg_name, pkg_version)| {
+                Submission::new(folder, pkg_name, pkg_version, snap.capture_time)
+            })
+            .collect();
+        let title = |snap: &Snapshot, i: usize| {
+            snap.submissions[i]
+                .description
+                .as_ref()
+                .and_then(|d| d.title.clone())
+        };
+
+        let source = FixtureSource::parse(include_str!("../tests/fixtures/incoming.transcript"));
+        enrich(
+            &mut snap,
+            || Ok(source),
+            "/incoming",
+            &history,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(title(&snap, 0).as_deref(), Some("Bazzing Things"));
+        // Not in the transcript, so the download failed
+        assert_eq!(title(&snap, 1), None);
+
+        // The cache answers without connecting to the server
+        snap.submissions.truncate(1);
+        snap.submissions[0].description = None;
+        enrich(
+            &mut snap,
+            || Err::<FixtureSource, _>("offline".into()),
+            "/incoming",
+            &history,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(title(&snap, 0).as_deref(), Some("Bazzing Things"));
+    }
+}
@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+
+use rocket::{
+    serde::Serialize,
+    tokio::sync::broadcast::{self, Receiver, Sender},
+};
+
+use crate::lifecycle::{self, Change, Transition};
+use crate::snapshot::Snapshot;
+
+static CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde", untagged)]
+pub enum QueueEvent {
+    SubmissionChanged {
+        time: DateTime<Utc>,
+        #[serde(flatten)]
+        change: Change,
+    },
+    SnapshotCompleted {
+        capture_time: DateTime<Utc>,
+        capture_duration: i64,
+        submissions: usize,
+    },
+    CaptureFailed {
+        time: DateTime<Utc>,
+        error: String,
+    },
+}
+
+impl QueueEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueueEvent::SubmissionChanged { change, .. } => match change.transition {
+                Transition::Added { .. } => "submission_added",
+                Transition::Moved { .. } => "submission_moved",
+                Transition::Removed { .. } => "submission_removed",
+            },
+            QueueEvent::SnapshotCompleted { .. } => "snapshot_completed",
+            QueueEvent::CaptureFailed { .. } => "capture_failed",
+        }
+    }
+}
+
+/// Fans out queue events to every subscriber, e.g. open `/events` streams.
+#[derive(Clone)]
+pub struct Events {
+    sender: Sender<QueueEvent>,
+}
+
+impl Events {
+    pub fn new() -> Events {
+        Events {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<QueueEvent> {
+        self.sender.subscribe()
+    }
+
+    fn publish(&self, event: QueueEvent) {
+        // Nobody listening is not an error
+        let _ = self.sender.send(event);
+    }
+
+    pub fn snapshot_captured(&self, prev: &Snapshot, next: &Snapshot) {
+        for change in lifecycle::diff(prev, next) {
+            self.publish(QueueEvent::SubmissionChanged {
+                time: next.capture_time,
+                change,
+            });
+        }
+        self.publish(QueueEvent::SnapshotCompleted {
+            capture_time: next.capture_time,
+            capture_duration: next.capture_duration,
+            submissions: next.submissions.len(),
+        });
+    }
+
+    pub fn capture_failed(&self, error: String) {
+        self.publish(QueueEvent::CaptureFailed {
+            time: Utc::now(),
+            error,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Submission;
+
+    #[test]
+    fn snapshot_captured_publishes_changes() {
+        let events = Events::new();
+        let mut receiver = events.subscribe();
+
+        let mut prev = Snapshot::new();
+        prev.submissions = vec![
+            Submission::new("pretest", "foo", "1.0", prev.capture_time),
+            Submission::new("waiting", "bar", "1.0", prev.capture_time),
+        ];
+        let mut next = Snapshot::new();
+        next.submissions = vec![
+            Submission::new("inspect", "foo", "1.0", next.capture_time),
+            Submission::new("newbies", "baz", "1.0", next.capture_time),
+        ];
+        events.snapshot_captured(&prev, &next);
+
+        let mut names = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            names.push(event.name());
+        }
+        assert_eq!(
+            names,
+            vec![
+                "submission_removed",
+                "submission_added",
+                "submission_moved",
+                "snapshot_completed"
+            ]
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt::Write;
+
+use crate::lifecycle::{ChangeRecord, Transition};
+
+pub static FEED_ENTRIES: usize = 100;
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn folder_name(folder: &str) -> &str {
+    if folder.is_empty() {
+        "the incoming root"
+    } else {
+        folder
+    }
+}
+
+fn title(record: &ChangeRecord) -> String {
+    let package = &record.change.package;
+    let what = match &record.change.transition {
+        Transition::Added { folder } => format!("entered {}", folder_name(folder)),
+        Transition::Moved { from, to } => {
+            format!("moved from {} to {}", folder_name(from), folder_name(to))
+        }
+        Transition::Removed { folder } => format!("left the queue from {}", folder_name(folder)),
+    };
+    format!("{} {} {}", package.pkg_name, package.pkg_version, what)
+}
+
+/// Renders queue changes as an Atom feed, `id` being the feed's unique URI.
+pub fn render(id: &str, title_text: &str, self_path: &str, records: &[ChangeRecord]) -> String {
+    let updated = records
+        .iter()
+        .map(|record| record.time)
+        .max()
+        .unwrap_or_else(Utc::now);
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    let _ = writeln!(xml, "  <id>{}</id>", escape(id));
+    let _ = writeln!(xml, "  <title>{}</title>", escape(title_text));
+    let _ = writeln!(xml, "  <updated>{}</updated>", timestamp(&updated));
+    let _ = writeln!(xml, "  <link rel=\"self\" href=\"{}\"/>", escape(self_path));
+    xml.push_str("  <author><name>cransubs</name></author>\n");
+
+    for record in records {
+        let package = &record.change.package;
+        let _ = writeln!(xml, "  <entry>");
+        let _ = writeln!(xml, "    <id>urn:cransubs:change:{}</id>", record.id);
+        let _ = writeln!(xml, "    <title>{}</title>", escape(&title(record)));
+        let _ = writeln!(xml, "    <updated>{}</updated>", timestamp(&record.time));
+        let _ = writeln!(
+            xml,
+            "    <link href=\"/package/{}\"/>",
+            escape(&package.pkg_name)
+        );
+        let _ = writeln!(
+            xml,
+            "    <category term=\"{}\"/>",
+            escape(&package.pkg_name)
+        );
+        let _ = writeln!(xml, "  </entry>");
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::{Change, PackageKey};
+    use chrono::TimeZone;
+
+    fn record(id: i64, minute: u32, pkg_name: &str, transition: Transition) -> ChangeRecord {
+        ChangeRecord {
+            id,
+            time: Utc.with_ymd_and_hms(2022, 10, 25, 12, minute, 0).unwrap(),
+            change: Change {
+                package: PackageKey {
+                    pkg_name: pkg_name.to_owned(),
+                    pkg_version: "1.0".to_owned(),
+                },
+                transition,
+            },
+        }
+    }
+
+    #[test]
+    fn render_escapes_entries() {
+        let records = [
+            record(
+                7,
+                30,
+                "foo",
+                Transition::Moved {
+                    from: "pretest".to_owned(),
+                    to: "waiting".to_owned(),
+                },
+            ),
+            record(
+                3,
+                10,
+                "a<b&\"c\"",
+                Transition::Added {
+                    folder: String::new(),
+                },
+            ),
+        ];
+        let xml = render("urn:test", "Tom & Jerry's <queue>", "/feed.atom", &records);
+
+        assert!(xml.contains("<title>Tom &amp; Jerry&apos;s &lt;queue&gt;</title>"));
+        // The feed was last updated by its newest entry
+        assert!(xml.contains("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n  <id>urn:test</id>"));
+        assert!(xml.contains("  <updated>2022-10-25T12:30:00Z</updated>\n  <link rel=\"self\""));
+
+        let entries: Vec<&str> = xml.split("<entry>").skip(1).collect();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].contains("<id>urn:cransubs:change:7</id>"));
+        assert!(entries[0].contains("<title>foo 1.0 moved from pretest to waiting</title>"));
+        assert!(entries[0].contains("<updated>2022-10-25T12:30:00Z</updated>"));
+        assert!(entries[1].contains("<id>urn:cransubs:change:3</id>"));
+        assert!(entries[1]
+            .contains("<title>a&lt;b&amp;&quot;c&quot; 1.0 entered the incoming root</title>"));
+        assert!(entries[1].contains("<updated>2022-10-25T12:10:00Z</updated>"));
+        assert!(entries[1].contains("<link href=\"/package/a&lt;b&amp;&quot;c&quot;\"/>"));
+    }
+
+    #[test]
+    fn title_describes_transitions() {
+        let removed = record(
+            1,
+            0,
+            "foo",
+            Transition::Removed {
+                folder: "publish".to_owned(),
+            },
+        );
+        assert_eq!(title(&removed), "foo 1.0 left the queue from publish");
+    }
+}
@@ -0,0 +1,256 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{cmp::Ordering, str::FromStr};
+
+use rocket::serde::{Deserialize, Serialize};
+
+lazy_static! {
+    // CRAN team members get a folder named after their initials, e.g. `KH`
+    static ref RE_REVIEWER: Regex = Regex::new(r"^[A-Z]{2,3}$").unwrap();
+}
+
+/// Processing stages of the CRAN incoming queue, in the order a submission usually
+/// passes them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Stage {
+    Incoming,
+    Pretest,
+    Inspect,
+    Newbies,
+    Human,
+    Pending,
+    Waiting,
+    Recheck,
+    Publish,
+    Archive,
+}
+
+impl Stage {
+    pub fn meaning(&self) -> &'static str {
+        match self {
+            Stage::Incoming => "Uploaded, automated checks have not started yet",
+            Stage::Pretest => "Automated checks are running",
+            Stage::Inspect => "Automated checks raised issues, waiting for manual inspection",
+            Stage::Newbies => "First submission of the package, waiting for manual inspection",
+            Stage::Human => "Assigned to a CRAN team member for review",
+            Stage::Pending => "A CRAN team member needs to take further action",
+            Stage::Waiting => "Waiting for an answer from the maintainer",
+            Stage::Recheck => "Reverse dependencies are being checked",
+            Stage::Publish => "Accepted, about to be published",
+            Stage::Archive => "Rejected or withdrawn",
+        }
+    }
+}
+
+impl FromStr for Stage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Stage, String> {
+        match s {
+            "" => Ok(Stage::Incoming),
+            "pretest" => Ok(Stage::Pretest),
+            "inspect" => Ok(Stage::Inspect),
+            "newbies" => Ok(Stage::Newbies),
+            "human" => Ok(Stage::Human),
+            "pending" => Ok(Stage::Pending),
+            "waiting" => Ok(Stage::Waiting),
+            "recheck" => Ok(Stage::Recheck),
+            "publish" => Ok(Stage::Publish),
+            "archive" => Ok(Stage::Archive),
+            _ => Err(format!("Unknown folder '{}'", s)),
+        }
+    }
+}
+
+/// Typed form of a folder path relative to the incoming root.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", tag = "kind", rename_all = "snake_case")]
+pub enum Folder {
+    /// A top-level folder, e.g. `pretest`, or the root itself
+    Stage {
+        stage: Stage,
+    },
+    /// A reviewer's folder, e.g. `KH`, or a stage within it, e.g. `KH/waiting`
+    Reviewer {
+        reviewer: String,
+        stage: Option<Stage>,
+    },
+    Other,
+}
+
+impl Folder {
+    pub fn parse(path: &str) -> Folder {
+        if let Ok(stage) = path.parse() {
+            return Folder::Stage { stage };
+        }
+
+        let (top, sub) = match path.split_once('/') {
+            Some((top, sub)) => (top, Some(sub)),
+            None => (path, None),
+        };
+        if !RE_REVIEWER.is_match(top) {
+            return Folder::Other;
+        }
+
+        match sub.map(str::parse) {
+            None => Folder::Reviewer {
+                reviewer: top.to_owned(),
+                stage: None,
+            },
+            Some(Ok(stage)) if stage != Stage::Incoming => Folder::Reviewer {
+                reviewer: top.to_owned(),
+                stage: Some(stage),
+            },
+            Some(_) => Folder::Other,
+        }
+    }
+
+    /// The stage a submission in this folder is at, a reviewer's own folder counting as
+    /// human review.
+    pub fn stage(&self) -> Option<Stage> {
+        match self {
+            Folder::Stage { stage } => Some(*stage),
+            Folder::Reviewer { stage, .. } => Some(stage.unwrap_or(Stage::Human)),
+            Folder::Other => None,
+        }
+    }
+
+    pub fn meaning(&self) -> String {
+        match self {
+            Folder::Stage { stage } => stage.meaning().to_owned(),
+            Folder::Reviewer {
+                reviewer,
+                stage: None,
+            } => format!("Assigned to CRAN team member {} for review", reviewer),
+            Folder::Reviewer {
+                reviewer,
+                stage: Some(stage),
+            } => format!("{} (handled by {})", stage.meaning(), reviewer),
+            Folder::Other => "Not a known CRAN incoming folder".to_owned(),
+        }
+    }
+
+    /// Position in the processing order, unknown folders last.
+    pub fn order(&self) -> u8 {
+        self.stage()
+            .map_or(Stage::Archive as u8 + 1, |stage| stage as u8)
+    }
+
+    fn reviewer(&self) -> Option<&str> {
+        match self {
+            Folder::Reviewer { reviewer, .. } => Some(reviewer),
+            _ => None,
+        }
+    }
+}
+
+impl Ord for Folder {
+    fn cmp(&self, other: &Folder) -> Ordering {
+        let key = |folder: &Folder| {
+            let stage = match folder {
+                Folder::Reviewer { stage, .. } => *stage,
+                _ => None,
+            };
+            (folder.order(), folder.reviewer().map(str::to_owned), stage)
+        };
+        key(self).cmp(&key(other))
+    }
+}
+
+impl PartialOrd for Folder {
+    fn partial_cmp(&self, other: &Folder) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A folder's classification as exposed next to its raw path.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FolderInfo {
+    #[serde(flatten)]
+    pub folder: Folder,
+    pub meaning: String,
+    pub order: u8,
+}
+
+impl FolderInfo {
+    pub fn new(path: &str) -> FolderInfo {
+        let folder = Folder::parse(path);
+        FolderInfo {
+            meaning: folder.meaning(),
+            order: folder.order(),
+            folder,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_classifies_folders() {
+        assert_eq!(
+            Folder::parse(""),
+            Folder::Stage {
+                stage: Stage::Incoming
+            }
+        );
+        assert_eq!(
+            Folder::parse("pretest"),
+            Folder::Stage {
+                stage: Stage::Pretest
+            }
+        );
+        assert_eq!(
+            Folder::parse("KH"),
+            Folder::Reviewer {
+                reviewer: "KH".to_owned(),
+                stage: None
+            }
+        );
+        assert_eq!(
+            Folder::parse("KH/waiting"),
+            Folder::Reviewer {
+                reviewer: "KH".to_owned(),
+                stage: Some(Stage::Waiting)
+            }
+        );
+        assert_eq!(Folder::parse("KH/waiting/deep"), Folder::Other);
+        assert_eq!(Folder::parse("KH/notes"), Folder::Other);
+        assert_eq!(Folder::parse("tmp"), Folder::Other);
+    }
+
+    #[test]
+    fn folders_sort_in_processing_order() {
+        let mut folders: Vec<_> = [
+            "tmp",
+            "publish",
+            "KH/waiting",
+            "waiting",
+            "KH",
+            "newbies",
+            "pretest",
+        ]
+        .into_iter()
+        .map(Folder::parse)
+        .collect();
+        folders.sort();
+
+        let stages: Vec<_> = folders.iter().map(Folder::stage).collect();
+        assert_eq!(
+            stages,
+            vec![
+                Some(Stage::Pretest),
+                Some(Stage::Newbies),
+                Some(Stage::Human),
+                Some(Stage::Waiting),
+                Some(Stage::Waiting),
+                Some(Stage::Publish),
+                None
+            ]
+        );
+        assert_eq!(folders[3], Folder::parse("waiting"));
+    }
+}
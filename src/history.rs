@@ -0,0 +1,976 @@
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, types::Type, Connection, OptionalExtension};
+use std::{
+    collections::HashMap,
+    error,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use rocket::tokio::task;
+
+use crate::description::Description;
+use crate::lifecycle::{self, Change, ChangeRecord, Journey, PackageKey, Stay, Transition};
+use crate::queue::Throughput;
+use crate::snapshot::{Snapshot, Submission};
+use crate::stats::ChangeTime;
+use crate::webhooks::{Delivery, DeliveryStatus, NewWebhook, PendingDelivery, Webhook};
+
+// Every entry is applied once, in order, and tracked through `PRAGMA user_version`.
+// Never edit an entry that has shipped, append a new one instead.
+static MIGRATIONS: &[&str] = &[
+    // 1: snapshots and the submissions seen in them
+    "CREATE TABLE snapshots (
+        id INTEGER PRIMARY KEY,
+        capture_time TEXT NOT NULL,
+        capture_duration INTEGER NOT NULL
+    );
+    CREATE TABLE submissions (
+        snapshot_id INTEGER NOT NULL REFERENCES snapshots(id) ON DELETE CASCADE,
+        request_time TEXT NOT NULL,
+        folder TEXT NOT NULL,
+        file_time TEXT NOT NULL,
+        file_bytes INTEGER NOT NULL,
+        pkg_name TEXT NOT NULL,
+        pkg_version TEXT NOT NULL
+    );
+    CREATE INDEX submissions_snapshot ON submissions(snapshot_id);
+    CREATE INDEX submissions_package ON submissions(pkg_name, pkg_version);",
+    // 2: per folder enter/leave times of every package version
+    "CREATE TABLE folder_stays (
+        id INTEGER PRIMARY KEY,
+        pkg_name TEXT NOT NULL,
+        pkg_version TEXT NOT NULL,
+        folder TEXT NOT NULL,
+        entered TEXT NOT NULL,
+        last_seen TEXT NOT NULL,
+        left TEXT
+    );
+    CREATE INDEX folder_stays_package ON folder_stays(pkg_name, pkg_version);
+    CREATE INDEX folder_stays_open ON folder_stays(left) WHERE left IS NULL;
+    INSERT INTO folder_stays (pkg_name, pkg_version, folder, entered, last_seen)
+        SELECT pkg_name, pkg_version, folder, request_time, request_time FROM submissions
+        WHERE snapshot_id = (SELECT max(id) FROM snapshots)
+        GROUP BY pkg_name, pkg_version, folder;",
+    // 3: webhook registrations and their delivery log
+    "CREATE TABLE webhooks (
+        id INTEGER PRIMARY KEY,
+        url TEXT NOT NULL,
+        pkg_name TEXT,
+        folder TEXT,
+        secret TEXT NOT NULL,
+        created TEXT NOT NULL
+    );
+    CREATE TABLE webhook_deliveries (
+        id INTEGER PRIMARY KEY,
+        webhook_id INTEGER NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+        event TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        status TEXT NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        response_status INTEGER,
+        last_error TEXT,
+        created TEXT NOT NULL,
+        updated TEXT NOT NULL
+    );
+    CREATE INDEX webhook_deliveries_webhook ON webhook_deliveries(webhook_id);",
+    // 4: log of the changes between consecutive snapshots
+    "CREATE TABLE submission_changes (
+        id INTEGER PRIMARY KEY,
+        time TEXT NOT NULL,
+        pkg_name TEXT NOT NULL,
+        pkg_version TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        from_folder TEXT,
+        to_folder TEXT
+    );
+    CREATE INDEX submission_changes_package ON submission_changes(pkg_name);",
+    // 5: file times may be unknown, record where each one came from. Older rows predate
+    // the distinction and are assumed to come from MDTM.
+    "CREATE TABLE submissions_new (
+        snapshot_id INTEGER NOT NULL REFERENCES snapshots(id) ON DELETE CASCADE,
+        request_time TEXT NOT NULL,
+        folder TEXT NOT NULL,
+        file_time TEXT,
+        file_time_source TEXT NOT NULL,
+        file_bytes INTEGER NOT NULL,
+        pkg_name TEXT NOT NULL,
+        pkg_version TEXT NOT NULL
+    );
+    INSERT INTO submissions_new
+        SELECT snapshot_id, request_time, folder, file_time, 'mdtm', file_bytes, pkg_name, pkg_version
+        FROM submissions ORDER BY rowid;
+    DROP TABLE submissions;
+    ALTER TABLE submissions_new RENAME TO submissions;
+    CREATE INDEX submissions_snapshot ON submissions(snapshot_id);
+    CREATE INDEX submissions_package ON submissions(pkg_name, pkg_version);",
+    // 6: DESCRIPTION metadata read from each package version's tarball, NULL if it had none
+    "CREATE TABLE descriptions (
+        pkg_name TEXT NOT NULL,
+        pkg_version TEXT NOT NULL,
+        description TEXT,
+        fetched TEXT NOT NULL,
+        PRIMARY KEY (pkg_name, pkg_version)
+    );",
+    // 7: time range lookups of /stats and the queue estimates, and pruning old snapshots
+    "CREATE INDEX snapshots_capture_time ON snapshots(capture_time);
+    CREATE INDEX folder_stays_left ON folder_stays(left);
+    CREATE INDEX submission_changes_time ON submission_changes(time);",
+];
+
+pub type HistoryError = Box<dyn error::Error + Send + Sync>;
+
+pub struct History {
+    conn: Mutex<Connection>,
+}
+
+impl History {
+    /// Runs `f` on the blocking pool. Queries hold the connection's lock while they run, so
+    /// async code must go through here rather than block the runtime.
+    pub async fn blocking<T, F>(self: &Arc<History>, f: F) -> Result<T, HistoryError>
+    where
+        F: FnOnce(&History) -> Result<T, HistoryError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let history = self.clone();
+        task::spawn_blocking(move || f(&history))
+            .await
+            .map_err(|err| format!("History task failed: {}", err))?
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<History, HistoryError> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        migrate(&mut conn)?;
+
+        Ok(History {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> Result<MutexGuard<'_, Connection>, HistoryError> {
+        self.conn
+            .lock()
+            .map_err(|_| "history connection poisoned".into())
+    }
+
+    pub fn record(&self, snap: &Snapshot) -> Result<i64, HistoryError> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+
+        let prev = load_latest(&tx)?.unwrap_or_else(Snapshot::new);
+        record_stays(&tx, &prev, snap)?;
+
+        tx.execute(
+            "INSERT INTO snapshots (capture_time, capture_duration) VALUES (?1, ?2)",
+            params![snap.capture_time, snap.capture_duration],
+        )?;
+        let snapshot_id = tx.last_insert_rowid();
+
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO submissions
+                    (snapshot_id, request_time, folder, file_time, file_time_source, file_bytes,
+                    pkg_name, pkg_version)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for sub in &snap.submissions {
+                insert.execute(params![
+                    snapshot_id,
+                    sub.request_time,
+                    sub.folder,
+                    sub.file_time,
+                    sub.file_time_source.as_str(),
+                    sub.file_bytes,
+                    sub.pkg_name,
+                    sub.pkg_version,
+                ])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(snapshot_id)
+    }
+
+    /// Deletes the submissions of snapshots captured before `before`, except the latest
+    /// snapshot's. Stays and changes are kept, so journeys and statistics are unaffected.
+    pub fn prune(&self, before: DateTime<Utc>) -> Result<usize, HistoryError> {
+        let conn = self.conn()?;
+
+        Ok(conn.execute(
+            "DELETE FROM submissions WHERE snapshot_id IN (
+                SELECT id FROM snapshots
+                WHERE capture_time < ?1 AND id < (SELECT max(id) FROM snapshots)
+            )",
+            [before],
+        )?)
+    }
+
+    pub fn latest(&self) -> Result<Option<Snapshot>, HistoryError> {
+        let conn = self.conn()?;
+        Ok(load_latest(&conn)?)
+    }
+
+    /// Departures per folder within `window` before `now`. The window shrinks to the
+    /// recorded history if that is shorter.
+    pub fn throughput(
+        &self,
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<Throughput, HistoryError> {
+        let conn = self.conn()?;
+
+        let first: Option<DateTime<Utc>> =
+            conn.query_row("SELECT min(capture_time) FROM snapshots", [], |row| row.get(0))?;
+        let since = match first {
+            Some(first) => first.max(now - window),
+            None => return Ok(Throughput::default()),
+        };
+
+        let mut select = conn.prepare(
+            "SELECT folder, count(*) FROM folder_stays WHERE left >= ?1 GROUP BY folder",
+        )?;
+        let departures = select
+            .query_map([since], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+
+        Ok(Throughput {
+            departures,
+            window: now - since,
+        })
+    }
+
+    /// Folder stays that began after the first capture and ended since `since`, so their
+    /// full duration is known.
+    pub fn closed_stays(&self, since: DateTime<Utc>) -> Result<Vec<Stay>, HistoryError> {
+        let conn = self.conn()?;
+
+        // Whatever the first capture found had been waiting for an unknown time already
+        let first = conn
+            .query_row(
+                "SELECT capture_time, capture_duration FROM snapshots ORDER BY id LIMIT 1",
+                [],
+                |row| Ok((row.get::<_, DateTime<Utc>>(0)?, row.get::<_, i64>(1)?)),
+            )
+            .optional()?;
+        let started = match first {
+            Some((capture_time, capture_duration)) => {
+                capture_time + Duration::milliseconds(capture_duration) + Duration::seconds(1)
+            }
+            None => return Ok(Vec::new()),
+        };
+
+        let mut select = conn.prepare(
+            "SELECT folder, entered, last_seen, left FROM folder_stays
+            WHERE left >= ?1 AND entered >= ?2 ORDER BY left, id",
+        )?;
+        let stays = select
+            .query_map(params![since, started], |row| stay_from_row(row, 0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(stays)
+    }
+
+    pub fn change_times(
+        &self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<ChangeTime>, HistoryError> {
+        let conn = self.conn()?;
+
+        let mut select = conn.prepare(
+            "SELECT time, kind FROM submission_changes WHERE time >= ?1 ORDER BY id",
+        )?;
+        let changes = select
+            .query_map([since], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(changes)
+    }
+
+    /// Cached descriptions of those `packages` whose tarball has been read, `None` meaning it
+    /// had no readable `DESCRIPTION`.
+    pub fn descriptions(
+        &self,
+        packages: &[PackageKey],
+    ) -> Result<HashMap<PackageKey, Option<Description>>, HistoryError> {
+        let conn = self.conn()?;
+
+        let mut select = conn.prepare(
+            "SELECT description FROM descriptions WHERE pkg_name = ?1 AND pkg_version = ?2",
+        )?;
+        let mut descriptions = HashMap::new();
+        for package in packages {
+            let row = select
+                .query_row(params![package.pkg_name, package.pkg_version], |row| {
+                    row.get::<_, Option<String>>(0)
+                })
+                .optional()?;
+            if let Some(json) = row {
+                let description = json
+                    .map(|json| rocket::serde::json::from_str(&json))
+                    .transpose()?;
+                descriptions.insert(package.clone(), description);
+            }
+        }
+
+        Ok(descriptions)
+    }
+
+    pub fn store_description(
+        &self,
+        package: &PackageKey,
+        description: Option<&Description>,
+    ) -> Result<(), HistoryError> {
+        let conn = self.conn()?;
+
+        let json = description
+            .map(rocket::serde::json::to_string)
+            .transpose()?;
+        conn.execute(
+            "INSERT OR REPLACE INTO descriptions (pkg_name, pkg_version, description, fetched)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![package.pkg_name, package.pkg_version, json, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    /// How often each of `packages` entered the queue, at least once as they were seen.
+    pub fn attempts(
+        &self,
+        packages: &[PackageKey],
+    ) -> Result<HashMap<PackageKey, usize>, HistoryError> {
+        let conn = self.conn()?;
+
+        let mut attempts = HashMap::new();
+        let mut versions: HashMap<&str, HashMap<String, usize>> = HashMap::new();
+        for package in packages {
+            if !versions.contains_key(package.pkg_name.as_str()) {
+                versions.insert(&package.pkg_name, count_attempts(&conn, &package.pkg_name)?);
+            }
+            let count = versions[package.pkg_name.as_str()]
+                .get(&package.pkg_version)
+                .copied()
+                .unwrap_or(1);
+            attempts.insert(package.clone(), count);
+        }
+
+        Ok(attempts)
+    }
+
+    pub fn journey(&self, package: &PackageKey) -> Result<Option<Journey>, HistoryError> {
+        Ok(self
+            .journeys(&package.pkg_name)?
+            .into_iter()
+            .find(|journey| journey.package == *package))
+    }
+
+    /// Journeys of every version of a package, oldest first.
+    pub fn journeys(&self, pkg_name: &str) -> Result<Vec<Journey>, HistoryError> {
+        let conn = self.conn()?;
+
+        let mut select = conn.prepare(
+            "SELECT pkg_version, folder, entered, last_seen, left FROM folder_stays
+            WHERE pkg_name = ?1 ORDER BY entered, id",
+        )?;
+        let rows = select
+            .query_map([pkg_name], |row| {
+                Ok((row.get::<_, String>(0)?, stay_from_row(row, 1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let attempts = count_attempts(&conn, pkg_name)?;
+
+        let mut versions: Vec<(String, Vec<Stay>)> = Vec::new();
+        for (pkg_version, stay) in rows {
+            match versions.iter_mut().find(|(v, _)| *v == pkg_version) {
+                Some((_, stays)) => stays.push(stay),
+                None => versions.push((pkg_version, vec![stay])),
+            }
+        }
+
+        Ok(versions
+            .into_iter()
+            .map(|(pkg_version, stays)| {
+                let package = PackageKey {
+                    pkg_name: pkg_name.to_owned(),
+                    pkg_version,
+                };
+                let attempts = attempts.get(&package.pkg_version).copied().unwrap_or(1);
+                Journey::new(package, stays, attempts)
+            })
+            .collect())
+    }
+
+    pub fn add_webhook(&self, webhook: &NewWebhook) -> Result<Webhook, HistoryError> {
+        let conn = self.conn()?;
+
+        let created = Utc::now();
+        conn.execute(
+            "INSERT INTO webhooks (url, pkg_name, folder, secret, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![webhook.url, webhook.pkg_name, webhook.folder, webhook.secret, created],
+        )?;
+
+        Ok(Webhook {
+            id: conn.last_insert_rowid(),
+            url: webhook.url.clone(),
+            pkg_name: webhook.pkg_name.clone(),
+            folder: webhook.folder.clone(),
+            secret: webhook.secret.clone(),
+            created,
+        })
+    }
+
+    pub fn webhooks(&self) -> Result<Vec<Webhook>, HistoryError> {
+        let conn = self.conn()?;
+
+        let mut select = conn.prepare(
+            "SELECT id, url, pkg_name, folder, secret, created FROM webhooks ORDER BY id",
+        )?;
+        let webhooks = select
+            .query_map([], |row| {
+                Ok(Webhook {
+                    id: row.get(0)?,
+                    url: row.get(1)?,
+                    pkg_name: row.get(2)?,
+                    folder: row.get(3)?,
+                    secret: row.get(4)?,
+                    created: row.get(5)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(webhooks)
+    }
+
+    /// Returns `false` if there was no such webhook.
+    pub fn delete_webhook(&self, id: i64) -> Result<bool, HistoryError> {
+        let conn = self.conn()?;
+
+        Ok(conn.execute("DELETE FROM webhooks WHERE id = ?1", [id])? > 0)
+    }
+
+    pub fn add_delivery(
+        &self,
+        webhook_id: i64,
+        event: &str,
+        payload: &str,
+    ) -> Result<i64, HistoryError> {
+        let conn = self.conn()?;
+
+        let now = Utc::now();
+        conn.execute(
+            "INSERT INTO webhook_deliveries (webhook_id, event, payload, status, created, updated)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+            params![
+                webhook_id,
+                event,
+                payload,
+                DeliveryStatus::Pending.as_str(),
+                now
+            ],
+        )?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn update_delivery(
+        &self,
+        id: i64,
+        status: DeliveryStatus,
+        attempts: u32,
+        response_status: Option<u16>,
+        last_error: Option<&str>,
+    ) -> Result<(), HistoryError> {
+        let conn = self.conn()?;
+
+        conn.execute(
+            "UPDATE webhook_deliveries
+            SET status = ?2, attempts = ?3, response_status = ?4, last_error = ?5, updated = ?6
+            WHERE id = ?1",
+            params![
+                id,
+                status.as_str(),
+                attempts,
+                response_status,
+                last_error,
+                Utc::now()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Deliveries of a webhook, newest first, or `None` if there is no such webhook.
+    pub fn deliveries(
+        &self,
+        webhook_id: i64,
+    ) -> Result<Option<Vec<Delivery>>, HistoryError> {
+        let conn = self.conn()?;
+
+        let exists = conn
+            .query_row("SELECT 1 FROM webhooks WHERE id = ?1", [webhook_id], |_| {
+                Ok(())
+            })
+            .optional()?;
+        if exists.is_none() {
+            return Ok(None);
+        }
+
+        let mut select = conn.prepare(
+            "SELECT id, webhook_id, event, payload, status, attempts, response_status, last_error,
+                created, updated
+            FROM webhook_deliveries WHERE webhook_id = ?1 ORDER BY id DESC",
+        )?;
+        let rows = select
+            .query_map([webhook_id], |row| {
+                Ok((
+                    Delivery {
+                        id: row.get(0)?,
+                        webhook_id: row.get(1)?,
+                        event: row.get(2)?,
+                        payload: Default::default(),
+                        status: DeliveryStatus::Pending,
+                        attempts: row.get(5)?,
+                        response_status: row.get(6)?,
+                        last_error: row.get(7)?,
+                        created: row.get(8)?,
+                        updated: row.get(9)?,
+                    },
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut deliveries = Vec::with_capacity(rows.len());
+        for (mut delivery, payload, status) in rows {
+            delivery.payload = rocket::serde::json::from_str(&payload)?;
+            delivery.status = status.parse()?;
+            deliveries.push(delivery);
+        }
+
+        Ok(Some(deliveries))
+    }
+
+    /// Deliveries still waiting for an attempt, oldest first, e.g. those interrupted by a
+    /// restart.
+    pub fn pending_deliveries(&self) -> Result<Vec<PendingDelivery>, HistoryError> {
+        let conn = self.conn()?;
+
+        let mut select = conn.prepare(
+            "SELECT d.id, d.event, d.payload, d.attempts,
+                w.id, w.url, w.pkg_name, w.folder, w.secret, w.created
+            FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook_id
+            WHERE d.status = ?1 ORDER BY d.id",
+        )?;
+        let pending = select
+            .query_map([DeliveryStatus::Pending.as_str()], |row| {
+                Ok(PendingDelivery {
+                    id: row.get(0)?,
+                    event: row.get(1)?,
+                    body: row.get(2)?,
+                    attempts: row.get(3)?,
+                    webhook: Webhook {
+                        id: row.get(4)?,
+                        url: row.get(5)?,
+                        pkg_name: row.get(6)?,
+                        folder: row.get(7)?,
+                        secret: row.get(8)?,
+                        created: row.get(9)?,
+                    },
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(pending)
+    }
+
+    /// Most recent changes, newest first, optionally limited to one package.
+    pub fn changes(
+        &self,
+        pkg_name: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<ChangeRecord>, HistoryError> {
+        let conn = self.conn()?;
+
+        let mut select = conn.prepare(
+            "SELECT id, time, pkg_name, pkg_version, kind, from_folder, to_folder
+            FROM submission_changes WHERE ?1 IS NULL OR pkg_name = ?1
+            ORDER BY id DESC LIMIT ?2",
+        )?;
+        let records = select
+            .query_map(params![pkg_name, limit], |row| {
+                let kind: String = row.get(4)?;
+                let from: Option<String> = row.get(5)?;
+                let to: Option<String> = row.get(6)?;
+                let transition = match (kind.as_str(), from, to) {
+                    ("added", _, Some(folder)) => Transition::Added { folder },
+                    ("moved", Some(from), Some(to)) => Transition::Moved { from, to },
+                    ("removed", Some(folder), _) => Transition::Removed { folder },
+                    _ => {
+                        return Err(rusqlite::Error::InvalidColumnType(
+                            4,
+                            kind,
+                            rusqlite::types::Type::Text,
+                        ))
+                    }
+                };
+
+                Ok(ChangeRecord {
+                    id: row.get(0)?,
+                    time: row.get(1)?,
+                    change: Change {
+                        package: PackageKey {
+                            pkg_name: row.get(2)?,
+                            pkg_version: row.get(3)?,
+                        },
+                        transition,
+                    },
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(records)
+    }
+}
+
+// A version enters the queue whenever it shows up after a snapshot without it. Appearing in
+// another folder while still queued is logged as added too, so replay the changes to know which
+// folders held it before each snapshot. Changes are only logged since migration 4, so older
+// visits are missing.
+fn count_attempts(conn: &Connection, pkg_name: &str) -> rusqlite::Result<HashMap<String, usize>> {
+    #[derive(Default)]
+    struct Replay {
+        folders: Vec<String>,
+        time: Option<DateTime<Utc>>,
+        queued: bool,
+        attempts: usize,
+    }
+
+    let mut select = conn.prepare_cached(
+        "SELECT time, pkg_version, kind, from_folder, to_folder FROM submission_changes
+        WHERE pkg_name = ?1 ORDER BY id",
+    )?;
+    let mut rows = select.query([pkg_name])?;
+
+    let mut versions: HashMap<String, Replay> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let time: DateTime<Utc> = row.get(0)?;
+        let replay = versions.entry(row.get(1)?).or_default();
+        let kind: String = row.get(2)?;
+        let from: Option<String> = row.get(3)?;
+        let to: Option<String> = row.get(4)?;
+
+        // All changes of one snapshot compare against the snapshot before it, and count once
+        if replay.time != Some(time) {
+            replay.time = Some(time);
+            replay.queued = !replay.folders.is_empty();
+        }
+        if kind == "added" && !replay.queued {
+            replay.attempts += 1;
+            replay.queued = true;
+        }
+
+        if let Some(from) = from {
+            replay.folders.retain(|folder| *folder != from);
+        }
+        replay.folders.extend(to);
+    }
+
+    Ok(versions
+        .into_iter()
+        .filter(|(_, replay)| replay.attempts > 0)
+        .map(|(pkg_version, replay)| (pkg_version, replay.attempts))
+        .collect())
+}
+
+fn stay_from_row(row: &rusqlite::Row, offset: usize) -> rusqlite::Result<Stay> {
+    Ok(Stay {
+        folder: row.get(offset)?,
+        entered: row.get(offset + 1)?,
+        last_seen: row.get(offset + 2)?,
+        left: row.get(offset + 3)?,
+    })
+}
+
+fn record_stays(conn: &Connection, prev: &Snapshot, next: &Snapshot) -> rusqlite::Result<()> {
+    let seen: HashMap<(&str, &str, &str), DateTime<Utc>> = next
+        .submissions
+        .iter()
+        .map(|sub| {
+            (
+                (
+                    sub.pkg_name.as_str(),
+                    sub.pkg_version.as_str(),
+                    sub.folder.as_str(),
+                ),
+                sub.request_time,
+            )
+        })
+        .collect();
+
+    let mut enter = conn.prepare(
+        "INSERT INTO folder_stays (pkg_name, pkg_version, folder, entered, last_seen)
+        VALUES (?1, ?2, ?3, ?4, ?4)",
+    )?;
+    let mut leave = conn.prepare(
+        "UPDATE folder_stays SET left = ?4
+        WHERE pkg_name = ?1 AND pkg_version = ?2 AND folder = ?3 AND left IS NULL",
+    )?;
+    let mut touch = conn.prepare(
+        "UPDATE folder_stays SET last_seen = ?4
+        WHERE pkg_name = ?1 AND pkg_version = ?2 AND folder = ?3 AND left IS NULL",
+    )?;
+    let mut log = conn.prepare(
+        "INSERT INTO submission_changes (time, pkg_name, pkg_version, kind, from_folder, to_folder)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+
+    for change in lifecycle::diff(prev, next) {
+        let PackageKey {
+            pkg_name,
+            pkg_version,
+        } = &change.package;
+        let (kind, left, entered) = match &change.transition {
+            Transition::Added { folder } => ("added", None, Some(folder)),
+            Transition::Moved { from, to } => ("moved", Some(from), Some(to)),
+            Transition::Removed { folder } => ("removed", Some(folder), None),
+        };
+        log.execute(params![
+            next.capture_time,
+            pkg_name,
+            pkg_version,
+            kind,
+            left,
+            entered
+        ])?;
+
+        if let Some(folder) = left {
+            leave.execute(params![pkg_name, pkg_version, folder, next.capture_time])?;
+        }
+        if let Some(folder) = entered {
+            let time = seen[&(pkg_name.as_str(), pkg_version.as_str(), folder.as_str())];
+            enter.execute(params![pkg_name, pkg_version, folder, time])?;
+        }
+    }
+
+    for ((pkg_name, pkg_version, folder), time) in &seen {
+        touch.execute(params![pkg_name, pkg_version, folder, time])?;
+    }
+
+    Ok(())
+}
+
+fn load_latest(conn: &Connection) -> rusqlite::Result<Option<Snapshot>> {
+    let head = conn
+        .query_row(
+            "SELECT id, capture_time, capture_duration FROM snapshots ORDER BY id DESC LIMIT 1",
+            [],
+            |row| Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+
+    let (snapshot_id, capture_time, capture_duration) = match head {
+        Some(head) => head,
+        None => return Ok(None),
+    };
+
+    let mut select = conn.prepare(
+        "SELECT s.request_time, s.folder, s.file_time, s.file_time_source, s.file_bytes, s.pkg_name,
+                s.pkg_version, d.description
+            FROM submissions s LEFT JOIN descriptions d USING (pkg_name, pkg_version)
+            WHERE s.snapshot_id = ?1 ORDER BY s.rowid",
+    )?;
+    let submissions = select
+        .query_map([snapshot_id], |row| {
+            let package = PackageKey {
+                pkg_name: row.get(5)?,
+                pkg_version: row.get(6)?,
+            };
+            let file_time_source = row.get::<_, String>(3)?.parse().map_err(|err: String| {
+                rusqlite::Error::FromSqlConversionFailure(3, Type::Text, err.into())
+            })?;
+            let sub = Submission::crawled(
+                &row.get::<_, String>(1)?,
+                package,
+                row.get(0)?,
+                row.get(2)?,
+                file_time_source,
+                row.get(4)?,
+            );
+            Ok(Submission {
+                description: row
+                    .get::<_, Option<String>>(7)?
+                    .map(|json| rocket::serde::json::from_str(&json))
+                    .transpose()
+                    .map_err(|err| {
+                        rusqlite::Error::FromSqlConversionFailure(7, Type::Text, err.into())
+                    })?,
+                ..sub
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Some(Snapshot {
+        capture_time,
+        captured: true,
+        capture_duration,
+        submissions,
+        unparsed_files: Vec::new(),
+    }))
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::JourneyStatus;
+    use chrono::TimeZone;
+
+    fn snapshot(minute: u32, folders: &[&str]) -> Snapshot {
+        let time = Utc.with_ymd_and_hms(2022, 10, 25, 12, minute, 0).unwrap();
+        Snapshot {
+            capture_time: time,
+            captured: true,
+            capture_duration: 0,
+            submissions: folders
+                .iter()
+                .map(|folder| Submission::new(folder, "foo", "1.0", time))
+                .collect(),
+            unparsed_files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn latest_returns_the_last_recorded_snapshot() {
+        let history = History::open(":memory:").unwrap();
+        assert!(history.latest().unwrap().is_none());
+
+        history.record(&snapshot(0, &["pretest"])).unwrap();
+        history.record(&snapshot(10, &["pretest", "waiting"])).unwrap();
+
+        let latest = history.latest().unwrap().unwrap();
+        assert_eq!(latest.capture_time, snapshot(10, &[]).capture_time);
+        let folders: Vec<_> = latest.submissions.iter().map(|s| s.folder.as_str()).collect();
+        assert_eq!(folders, vec!["pretest", "waiting"]);
+    }
+
+    #[test]
+    fn record_tracks_journeys_and_changes() {
+        let history = History::open(":memory:").unwrap();
+        for (minute, folders) in [
+            (0, &["pretest"][..]),
+            (10, &["pretest"]),
+            (20, &["waiting"]),
+            (30, &["publish"]),
+            (40, &[]),
+        ] {
+            history.record(&snapshot(minute, folders)).unwrap();
+        }
+
+        let journey = history
+            .journey(&PackageKey {
+                pkg_name: "foo".to_owned(),
+                pkg_version: "1.0".to_owned(),
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(journey.status, JourneyStatus::Published);
+        let folders: Vec<_> = journey.stays.iter().map(|s| s.folder.as_str()).collect();
+        assert_eq!(folders, vec!["pretest", "waiting", "publish"]);
+        assert_eq!(journey.stays[0].last_seen, snapshot(10, &[]).capture_time);
+        assert_eq!(journey.stays[0].left, Some(snapshot(20, &[]).capture_time));
+
+        let changes = history.changes(Some("foo"), 10).unwrap();
+        let transitions: Vec<_> = changes.iter().map(|r| &r.change.transition).collect();
+        assert_eq!(
+            transitions,
+            vec![
+                &Transition::Removed {
+                    folder: "publish".to_owned()
+                },
+                &Transition::Moved {
+                    from: "waiting".to_owned(),
+                    to: "publish".to_owned()
+                },
+                &Transition::Moved {
+                    from: "pretest".to_owned(),
+                    to: "waiting".to_owned()
+                },
+                &Transition::Added {
+                    folder: "pretest".to_owned()
+                },
+            ]
+        );
+        assert!(history.changes(Some("bar"), 10).unwrap().is_empty());
+
+        let now = snapshot(40, &[]).capture_time;
+        let throughput = history.throughput(Duration::days(7), now).unwrap();
+        assert_eq!(throughput.window, Duration::minutes(40));
+        assert_eq!(throughput.departures.get("pretest"), Some(&1));
+        assert_eq!(throughput.departures.get("waiting"), Some(&1));
+
+        // The first capture's pretest stay began at an unknown time
+        let stays = history.closed_stays(snapshot(0, &[]).capture_time).unwrap();
+        let folders: Vec<_> = stays.iter().map(|s| s.folder.as_str()).collect();
+        assert_eq!(folders, vec!["waiting", "publish"]);
+        assert_eq!(history.change_times(snapshot(15, &[]).capture_time).unwrap().len(), 3);
+
+        // Submitted again after it left the queue
+        history.record(&snapshot(50, &["pretest"])).unwrap();
+        let package = snapshot(50, &["pretest"]).submissions[0].package();
+        assert_eq!(history.attempts(std::slice::from_ref(&package)).unwrap()[&package], 2);
+        assert_eq!(history.journey(&package).unwrap().unwrap().attempts, 2);
+    }
+
+    #[test]
+    fn attempts_ignore_copies_in_other_folders() {
+        let history = History::open(":memory:").unwrap();
+        let package = snapshot(0, &["pretest"]).submissions[0].package();
+        let attempts = |history: &History| history.attempts(std::slice::from_ref(&package)).unwrap()[&package];
+
+        // Showing up in two folders at once is one attempt
+        history.record(&snapshot(0, &["pretest", "inspect"])).unwrap();
+        assert_eq!(attempts(&history), 1);
+
+        // So is a copy appearing in another folder while the version is still queued
+        for (minute, folders) in [(10, &["pretest"][..]), (20, &["pretest", "waiting"]), (30, &["waiting"])] {
+            history.record(&snapshot(minute, folders)).unwrap();
+        }
+        assert_eq!(attempts(&history), 1);
+
+        history.record(&snapshot(40, &[])).unwrap();
+        history.record(&snapshot(50, &["pretest"])).unwrap();
+        assert_eq!(attempts(&history), 2);
+    }
+
+    #[test]
+    fn prune_keeps_the_latest_snapshot_and_attempts() {
+        let history = History::open(":memory:").unwrap();
+        let package = snapshot(0, &["pretest"]).submissions[0].package();
+        for (minute, folders) in [(0, &["pretest"][..]), (10, &[]), (20, &["pretest", "waiting"])] {
+            history.record(&snapshot(minute, folders)).unwrap();
+        }
+
+        assert_eq!(history.prune(snapshot(30, &[]).capture_time).unwrap(), 1);
+        let latest = history.latest().unwrap().unwrap();
+        assert_eq!(latest.capture_time, snapshot(20, &[]).capture_time);
+        assert_eq!(latest.submissions.len(), 2);
+        let attempts = history.attempts(std::slice::from_ref(&package)).unwrap();
+        assert_eq!(attempts[&package], 2);
+    }
+}
@@ -0,0 +1,146 @@
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::folder::{Folder, Stage};
+use crate::snapshot::Snapshot;
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PackageKey {
+    pub pkg_name: String,
+    pub pkg_version: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", tag = "kind", rename_all = "snake_case")]
+pub enum Transition {
+    Added { folder: String },
+    Moved { from: String, to: String },
+    Removed { folder: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Change {
+    #[serde(flatten)]
+    pub package: PackageKey,
+    #[serde(flatten)]
+    pub transition: Transition,
+}
+
+/// A change as recorded in the history database.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChangeRecord {
+    pub id: i64,
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+/// Time spent by one package version in one folder.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Stay {
+    pub folder: String,
+    pub entered: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub left: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum JourneyStatus {
+    InQueue,
+    Published,
+    Vanished,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Journey {
+    #[serde(flatten)]
+    pub package: PackageKey,
+    pub status: JourneyStatus,
+    /// How often the version entered the queue
+    pub attempts: usize,
+    pub stays: Vec<Stay>,
+}
+
+impl Journey {
+    pub fn new(package: PackageKey, stays: Vec<Stay>, attempts: usize) -> Journey {
+        let publish = Folder::Stage {
+            stage: Stage::Publish,
+        };
+        let status = if stays.iter().any(|s| s.left.is_none()) {
+            JourneyStatus::InQueue
+        } else if stays
+            .last()
+            .is_some_and(|s| Folder::parse(&s.folder) == publish)
+        {
+            JourneyStatus::Published
+        } else {
+            JourneyStatus::Vanished
+        };
+
+        Journey {
+            package,
+            status,
+            attempts,
+            stays,
+        }
+    }
+}
+
+fn folders_by_package(snap: &Snapshot) -> BTreeMap<PackageKey, BTreeSet<&str>> {
+    let mut map: BTreeMap<PackageKey, BTreeSet<&str>> = BTreeMap::new();
+    for sub in &snap.submissions {
+        map.entry(sub.package()).or_default().insert(&sub.folder);
+    }
+    map
+}
+
+/// Changes that turn `prev` into `next`, ordered by package.
+///
+/// A package that left one folder and entered another between the two snapshots is
+/// reported as moved, everything else as added or removed.
+pub fn diff(prev: &Snapshot, next: &Snapshot) -> Vec<Change> {
+    let prev = folders_by_package(prev);
+    let next = folders_by_package(next);
+    let empty = BTreeSet::new();
+
+    let keys: BTreeSet<&PackageKey> = prev.keys().chain(next.keys()).collect();
+    let mut changes = Vec::new();
+
+    for key in keys {
+        let before = prev.get(key).unwrap_or(&empty);
+        let after = next.get(key).unwrap_or(&empty);
+
+        let mut left = before.difference(after);
+        let mut entered = after.difference(before);
+
+        loop {
+            let transition = match (left.next(), entered.next()) {
+                (Some(from), Some(to)) => Transition::Moved {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                },
+                (Some(folder), None) => Transition::Removed {
+                    folder: folder.to_string(),
+                },
+                (None, Some(folder)) => Transition::Added {
+                    folder: folder.to_string(),
+                },
+                (None, None) => break,
+            };
+            changes.push(Change {
+                package: key.clone(),
+                transition,
+            });
+        }
+    }
+
+    changes
+}
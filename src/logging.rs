@@ -0,0 +1,133 @@
+use std::{
+    convert::Infallible,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+use tracing::{info, info_span, Span};
+use tracing_subscriber::EnvFilter;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    request::{FromRequest, Outcome},
+    serde::{Deserialize, Serialize},
+    Data, Request, Response,
+};
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Installs the global subscriber. `filter` uses the `RUST_LOG` directive syntax,
+/// e.g. `info,cransubs::snapshot=debug`.
+pub fn init(format: LogFormat, filter: &str) -> Result<(), String> {
+    let filter =
+        EnvFilter::try_new(filter).map_err(|err| format!("Invalid log filter: {}", err))?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    }
+    .map_err(|err| err.to_string())
+}
+
+/// Wraps every request in a span carrying a request id, returned as `X-Request-Id`, and logs
+/// its outcome.
+pub struct RequestLog;
+
+#[derive(Clone)]
+struct RequestContext(Option<(u64, Span, Instant)>);
+
+/// The current request's span, for handlers to log within.
+///
+/// Handlers can't run inside the span themselves, so log through `in_scope`.
+pub struct RequestSpan(Span);
+
+impl RequestSpan {
+    pub fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.0.in_scope(f)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestSpan {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<RequestSpan, Infallible> {
+        let span = match request.local_cache(|| RequestContext(None)) {
+            RequestContext(Some((_, span, _))) => span.clone(),
+            RequestContext(None) => Span::none(),
+        };
+        Outcome::Success(RequestSpan(span))
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Log HTTP requests",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        let span = info_span!(
+            "request",
+            request_id = id,
+            method = %request.method(),
+            uri = %request.uri(),
+        );
+        request.local_cache(|| RequestContext(Some((id, span, Instant::now()))));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if let RequestContext(Some((id, span, start))) =
+            request.local_cache(|| RequestContext(None))
+        {
+            response.set_header(Header::new("X-Request-Id", id.to_string()));
+            span.in_scope(|| {
+                info!(
+                    status = response.status().code,
+                    route = request.route().and_then(|r| r.name.as_deref()),
+                    duration_ms = start.elapsed().as_millis() as u64,
+                    "request finished"
+                )
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::{get, local::asynchronous::Client, routes};
+
+    #[get("/")]
+    fn index() {}
+
+    #[rocket::async_test]
+    async fn request_log_sets_request_ids() {
+        let rocket = rocket::build()
+            .attach(RequestLog)
+            .mount("/", routes![index]);
+        let client = Client::untracked(rocket).await.unwrap();
+
+        let request_id = |res: &rocket::local::asynchronous::LocalResponse| {
+            res.headers().get_one("X-Request-Id").map(str::to_owned)
+        };
+        let first = request_id(&client.get("/").dispatch().await);
+        // Unmatched requests are logged too
+        let second = request_id(&client.get("/missing").dispatch().await);
+        assert!(first.is_some());
+        assert!(second.is_some());
+        assert_ne!(first, second);
+    }
+}
@@ -1,100 +1,349 @@
 #[macro_use]
 extern crate rocket;
+mod compression;
+mod conditional;
+mod config;
+mod cors;
+mod cran;
+mod description;
+mod events;
+mod feed;
+mod folder;
+mod history;
+mod lifecycle;
+mod logging;
+mod metrics;
+mod package_file;
+mod query;
+mod queue;
+mod refresh;
+mod resubmission;
 mod snapshot;
+mod stats;
+mod source;
+mod version;
+mod webhooks;
+use chrono::{DateTime, Utc};
 use rocket::{
+    response::{status::{self, BadRequest}, stream::{Event, EventStream}},
+    tokio::{select, sync::broadcast::error::RecvError},
+    Shutdown,
     serde::{Deserialize, Serialize, json},
-    tokio::sync::{Mutex, RwLock},
-    State, fairing::{Fairing, Info, Kind}, Request, Response, http::Header, Config,
+    tokio::sync::RwLock,
+    State, http::{ContentType, Status}, Config,
 };
-use std::{
-    sync::Arc,
-    time::{SystemTime, UNIX_EPOCH}, net::Ipv4Addr,
-};
-
-static TIMEOUT_CACHE_SECONDS: u64 = 60*10;
-
+use std::{process, sync::Arc};
+use tracing::error;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 struct SnapshotContainer {
     update_interval: u64,
+    /// `None` until a capture succeeded
+    age_seconds: Option<i64>,
+    next_update: Option<DateTime<Utc>>,
     snapshot: snapshot::Snapshot,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct PackageContainer {
+    pkg_name: String,
+    capture_time: DateTime<Utc>,
+    submissions: Vec<snapshot::Submission>,
+    history: Option<Vec<lifecycle::Journey>>,
+}
+
 pub struct Cache {
-    last_update: Arc<Mutex<SystemTime>>,
     data: Arc<RwLock<SnapshotContainer>>,
 }
 
-pub struct CORS;
+#[get("/")]
+fn index() -> &'static str {
+    "Hello, CRAN!"
+}
+
+#[get("/snap?<query..>")]
+async fn snap(query: query::SnapQuery<'_>, preconditions: conditional::Preconditions, cache: &State<Cache>) -> Result<conditional::Cached<json::Json<SnapshotContainer>>, BadRequest<String>> {
+    let filter = query.compile().map_err(BadRequest)?;
+
+    let mut container = cache.data.read().await.clone();
+    let now = Utc::now();
+    container.age_seconds = container
+        .snapshot
+        .captured
+        .then(|| now.signed_duration_since(container.snapshot.capture_time).num_seconds());
+    filter.apply(&mut container.snapshot.submissions);
+
+    // Clients may reuse the response until the next refresh is due, unless nothing was
+    // captured yet
+    let etag = conditional::etag(&container.snapshot);
+    let last_modified = container.snapshot.capture_time;
+    let max_age = container
+        .next_update
+        .filter(|_| container.snapshot.captured)
+        .map(|t| t.signed_duration_since(now).num_seconds());
+    Ok(conditional::Cached::new(json::Json(container), etag, last_modified, max_age, &preconditions))
+}
+
+#[get("/package/<name>")]
+async fn package(name: &str, cache: &State<Cache>, span: logging::RequestSpan, history: &State<Arc<history::History>>) -> Option<json::Json<PackageContainer>> {
+    let (capture_time, submissions) = {
+        let data = cache.data.read().await;
+        let submissions: Vec<snapshot::Submission> = data
+            .snapshot
+            .submissions
+            .iter()
+            .filter(|sub| sub.pkg_name == name)
+            .cloned()
+            .collect();
+        (data.snapshot.capture_time, submissions)
+    };
 
-#[rocket::async_trait]
-impl Fairing for CORS {
-    fn info(&self) -> Info {
-        Info {
-            name: "Add CORS headers to responses",
-            kind: Kind::Response
+    if submissions.is_empty() {
+        return None;
+    }
+
+    let pkg_name = name.to_owned();
+    let history = match history.blocking(move |history| history.journeys(&pkg_name)).await {
+        Ok(journeys) => Some(journeys),
+        Err(err) => {
+            span.in_scope(|| error!(pkg_name = name, error = %err, "could not load package history"));
+            None
+        }
+    };
+
+    Some(json::Json(PackageContainer {
+        pkg_name: name.to_owned(),
+        capture_time,
+        submissions,
+        history,
+    }))
+}
+
+#[get("/journey/<name>/<version>")]
+async fn journey(name: &str, version: &str, span: logging::RequestSpan, history: &State<Arc<history::History>>) -> Result<Option<json::Json<lifecycle::Journey>>, Status> {
+    let package = lifecycle::PackageKey {
+        pkg_name: name.to_owned(),
+        pkg_version: version.to_owned(),
+    };
+
+    match history.blocking(move |history| history.journey(&package)).await {
+        Ok(journey) => Ok(journey.map(json::Json)),
+        Err(err) => {
+            span.in_scope(|| error!(pkg_name = name, pkg_version = version, error = %err, "could not load journey"));
+            Err(Status::InternalServerError)
         }
     }
+}
+
+#[get("/stats?<days>")]
+async fn processing_stats(days: Option<u32>, span: logging::RequestSpan, history: &State<Arc<history::History>>) -> Result<json::Json<stats::Stats>, status::Custom<String>> {
+    let days = days.unwrap_or(stats::DEFAULT_WINDOW_DAYS);
+    if days == 0 {
+        return Err(status::Custom(Status::BadRequest, "days must be at least 1".to_owned()));
+    }
 
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Methods", "POST, GET, PATCH, OPTIONS"));
-        response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+    let until = Utc::now();
+    let since = until
+        .checked_sub_signed(chrono::Duration::days(days.into()))
+        .ok_or_else(|| status::Custom(Status::BadRequest, format!("days is out of range: {}", days)))?;
+    let loaded = history
+        .blocking(move |history| Ok((history.closed_stays(since)?, history.change_times(since)?)))
+        .await;
+
+    match loaded {
+        Ok((stays, changes)) => Ok(json::Json(stats::compute(since, until, &stays, &changes))),
+        Err(err) => {
+            span.in_scope(|| error!(error = %err, "could not load statistics"));
+            Err(status::Custom(Status::InternalServerError, "Could not load statistics".to_owned()))
+        }
     }
 }
 
-#[get("/")]
-fn index() -> &'static str {
-    "Hello, CRAN!"
+#[get("/events")]
+fn event_stream(events: &State<events::Events>, mut shutdown: Shutdown) -> EventStream![] {
+    let mut receiver = events.subscribe();
+    EventStream! {
+        loop {
+            let event = select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut shutdown => break,
+            };
+            yield Event::json(&event).event(event.name());
+        }
+    }
+}
+
+#[get("/metrics")]
+async fn metrics_text(cache: &State<Cache>) -> (ContentType, String) {
+    let text = metrics::render(&cache.data.read().await.snapshot);
+    (ContentType::new("text", "plain").with_params(("version", "0.0.4")), text)
 }
 
-#[get("/snap")]
-async fn snap(cache: &State<Cache>) -> json::Json<SnapshotContainer> {
-    {
-        let mut last_update = cache.last_update.lock().await;
-
-        let now = SystemTime::now();
-
-        if now
-            .duration_since(*last_update)
-            .expect("Time went backwards")
-            .as_secs()
-            > TIMEOUT_CACHE_SECONDS
-        {
-            println!("Update cache");
-            *last_update = now;
-            let mut x = cache.data.write().await;
-            match snapshot::Snapshot::capture() {
-                Ok(snap) => x.snapshot = snap,
-                Err(err) => println!("ERROR: Could not create snapshot: {}", err),
-            }
-        } else {
-            println!("Use cached");
+#[get("/feed.atom")]
+async fn queue_feed(span: logging::RequestSpan, history: &State<Arc<history::History>>) -> Result<(ContentType, String), Status> {
+    match history.blocking(|history| history.changes(None, feed::FEED_ENTRIES)).await {
+        Ok(records) => Ok((
+            ContentType::new("application", "atom+xml"),
+            feed::render("urn:cransubs:feed", "CRAN incoming queue", "/feed.atom", &records),
+        )),
+        Err(err) => {
+            span.in_scope(|| error!(error = %err, "could not load queue changes"));
+            Err(Status::InternalServerError)
         }
     }
+}
 
-    json::Json(cache.data.read().await.clone())
+#[get("/package/<name>/feed.atom")]
+async fn package_feed(name: &str, span: logging::RequestSpan, history: &State<Arc<history::History>>) -> Result<(ContentType, String), Status> {
+    let pkg_name = name.to_owned();
+    match history.blocking(move |history| history.changes(Some(&pkg_name), feed::FEED_ENTRIES)).await {
+        Ok(records) => Ok((
+            ContentType::new("application", "atom+xml"),
+            feed::render(
+                &format!("urn:cransubs:feed:package:{}", name),
+                &format!("CRAN incoming queue: {}", name),
+                &format!("/package/{}/feed.atom", name),
+                &records,
+            ),
+        )),
+        Err(err) => {
+            span.in_scope(|| error!(pkg_name = name, error = %err, "could not load queue changes"));
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[post("/webhooks", data = "<webhook>")]
+async fn add_webhook(_auth: webhooks::Authorized, webhook: json::Json<webhooks::NewWebhook>, span: logging::RequestSpan, settings: &State<Arc<config::Settings>>, history: &State<Arc<history::History>>) -> Result<status::Created<json::Json<webhooks::Webhook>>, status::Custom<String>> {
+    webhook
+        .validate(settings.webhook_allow_private_addresses)
+        .await
+        .map_err(|err| status::Custom(Status::BadRequest, err))?;
+
+    let webhook = webhook.into_inner();
+    match history.blocking(move |history| history.add_webhook(&webhook)).await {
+        Ok(webhook) => Ok(status::Created::new(format!("/webhooks/{}", webhook.id)).body(json::Json(webhook))),
+        Err(err) => {
+            span.in_scope(|| error!(error = %err, "could not store webhook"));
+            Err(status::Custom(Status::InternalServerError, "Could not store webhook".to_owned()))
+        }
+    }
+}
+
+#[get("/webhooks")]
+async fn list_webhooks(_auth: webhooks::Authorized, span: logging::RequestSpan, history: &State<Arc<history::History>>) -> Result<json::Json<Vec<webhooks::Webhook>>, Status> {
+    match history.blocking(|history| history.webhooks()).await {
+        Ok(webhooks) => Ok(json::Json(webhooks)),
+        Err(err) => {
+            span.in_scope(|| error!(error = %err, "could not load webhooks"));
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[delete("/webhooks/<id>")]
+async fn delete_webhook(_auth: webhooks::Authorized, id: i64, span: logging::RequestSpan, history: &State<Arc<history::History>>) -> Result<Option<status::NoContent>, Status> {
+    match history.blocking(move |history| history.delete_webhook(id)).await {
+        Ok(deleted) => Ok(deleted.then_some(status::NoContent)),
+        Err(err) => {
+            span.in_scope(|| error!(webhook_id = id, error = %err, "could not delete webhook"));
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[get("/webhooks/<id>/deliveries")]
+async fn webhook_deliveries(_auth: webhooks::Authorized, id: i64, span: logging::RequestSpan, history: &State<Arc<history::History>>) -> Result<Option<json::Json<Vec<webhooks::Delivery>>>, Status> {
+    match history.blocking(move |history| history.deliveries(id)).await {
+        Ok(deliveries) => Ok(deliveries.map(json::Json)),
+        Err(err) => {
+            span.in_scope(|| error!(webhook_id = id, error = %err, "could not load webhook deliveries"));
+            Err(Status::InternalServerError)
+        }
+    }
 }
 
 #[launch]
 fn rocket() -> _ {
+    let settings = config::Settings::load().unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {}", err);
+        process::exit(1)
+    });
+    logging::init(settings.log_format, &settings.log_filter).unwrap_or_else(|err| {
+        eprintln!("Could not set up logging: {}", err);
+        process::exit(1)
+    });
+
+    let cors = cors::Cors::new(&settings).unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {}", err);
+        process::exit(1)
+    });
+
     let config = Config {
-        port: 8080,
-        address: Ipv4Addr::new(0, 0, 0, 0).into(),
+        port: settings.port,
+        address: settings.address,
         ..Config::debug_default()
     };
 
+    let history = history::History::open(&settings.database)
+        .unwrap_or_else(|err| panic!("Could not open history database '{}': {}", settings.database.display(), err));
+
+    // Serve the last stored snapshot until the first capture succeeds
+    let (last_capture, mut snapshot) = match history.latest() {
+        Ok(Some(snap)) => (Some(snap.capture_time), snap),
+        Ok(None) => (None, snapshot::Snapshot::new()),
+        Err(err) => {
+            error!(error = %err, "could not load snapshot history");
+            (None, snapshot::Snapshot::new())
+        }
+    };
+
+    queue::estimate(&mut snapshot, &history);
+    resubmission::detect(&mut snapshot, &history);
+    metrics::observe_snapshot(&snapshot);
+
+    let settings = Arc::new(settings);
+    let history = Arc::new(history);
+    let events = events::Events::new();
+    let data = Arc::new(RwLock::new(SnapshotContainer {
+        update_interval: settings.refresh_interval,
+        age_seconds: None,
+        next_update: None,
+        snapshot,
+    }));
+
     rocket::build()
         .configure(config)
-        .attach(CORS)
-        .manage(Cache {
-            last_update: Arc::new(Mutex::new(UNIX_EPOCH)),
-            data: Arc::new(RwLock::new(SnapshotContainer {
-                update_interval: TIMEOUT_CACHE_SECONDS,
-                snapshot: snapshot::Snapshot::new(),
-            })),
-        })
-        .mount("/", routes![index, snap])
+        .attach(cors)
+        .attach(compression::Compression)
+        .attach(logging::RequestLog)
+        .attach(metrics::RequestMetrics)
+        .attach(webhooks::Dispatcher::new(history.clone(), events.clone(), settings.webhook_allow_private_addresses))
+        .attach(refresh::Refresher::new(data.clone(), history.clone(), events.clone(), settings.clone(), last_capture))
+        .manage(Cache { data })
+        .manage(settings)
+        .manage(history)
+        .manage(events)
+        .mount("/", routes![
+            index,
+            cors::preflight,
+            snap,
+            package,
+            journey,
+            processing_stats,
+            event_stream,
+            metrics_text,
+            queue_feed,
+            package_feed,
+            add_webhook,
+            list_webhooks,
+            delete_webhook,
+            webhook_deliveries
+        ])
 }
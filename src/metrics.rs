@@ -0,0 +1,172 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::{collections::HashMap, time::Instant};
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Data, Request, Response,
+};
+
+use crate::snapshot::Snapshot;
+
+lazy_static! {
+    static ref REGISTRY: Registry =
+        Registry::new_custom(Some("cransubs".to_owned()), None).unwrap();
+    static ref SUBMISSIONS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new(
+            "submissions",
+            "Submissions per CRAN incoming folder in the cached snapshot"
+        ),
+        &["folder"]
+    ));
+    static ref QUEUE_SIZE: IntGauge = register(IntGauge::new(
+        "queue_size",
+        "Submissions in the cached snapshot"
+    ));
+    static ref CAPTURE_DURATION: Gauge = register(Gauge::new(
+        "capture_duration_seconds",
+        "Duration of the capture that produced the cached snapshot"
+    ));
+    static ref SNAPSHOT_AGE: Gauge = register(Gauge::new(
+        "snapshot_age_seconds",
+        "Time since the cached snapshot was captured, +Inf until a capture succeeded"
+    ));
+    static ref CAPTURES: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("captures_total", "Capture attempts by result"),
+        &["result"]
+    ));
+    static ref FTP_COMMANDS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("ftp_commands_total", "FTP commands sent to the CRAN server"),
+        &["command"]
+    ));
+    static ref HTTP_REQUESTS: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("http_request_duration_seconds", "HTTP request latencies"),
+        &["method", "route", "status"]
+    ));
+}
+
+fn register<M: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<M>) -> M {
+    let metric = metric.unwrap();
+    REGISTRY.register(Box::new(metric.clone())).unwrap();
+    metric
+}
+
+/// Updates the gauges describing the cached snapshot.
+pub fn observe_snapshot(snap: &Snapshot) {
+    let mut folders: HashMap<&str, i64> = HashMap::new();
+    for sub in &snap.submissions {
+        *folders.entry(&sub.folder).or_default() += 1;
+    }
+
+    SUBMISSIONS.reset();
+    for (folder, count) in folders {
+        SUBMISSIONS.with_label_values(&[folder]).set(count);
+    }
+    QUEUE_SIZE.set(snap.submissions.len() as i64);
+    CAPTURE_DURATION.set(snap.capture_duration as f64 / 1000.0);
+}
+
+pub fn capture_finished(success: bool) {
+    let result = if success { "success" } else { "failure" };
+    CAPTURES.with_label_values(&[result]).inc();
+}
+
+pub fn ftp_command(command: &str) {
+    FTP_COMMANDS.with_label_values(&[command]).inc();
+}
+
+/// Renders all metrics in the Prometheus text format.
+pub fn render(snap: &Snapshot) -> String {
+    if snap.captured {
+        let age = chrono::Utc::now().signed_duration_since(snap.capture_time);
+        SNAPSHOT_AGE.set(age.num_milliseconds() as f64 / 1000.0);
+    } else {
+        SNAPSHOT_AGE.set(f64::INFINITY);
+    }
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("metrics encode as text");
+    String::from_utf8(buffer).expect("metrics text is UTF-8")
+}
+
+/// Records the latency of every HTTP request.
+pub struct RequestMetrics;
+
+#[derive(Clone, Copy)]
+struct RequestStart(Option<Instant>);
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Record HTTP request metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Some(Instant::now())));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let start = request.local_cache(|| RequestStart(None));
+        if let Some(start) = start.0 {
+            // Label by route template, not by path, to keep the number of series bounded
+            let route = request.route().map_or_else(
+                || "unmatched".to_owned(),
+                |r| r.uri.origin.path().to_string(),
+            );
+            HTTP_REQUESTS
+                .with_label_values(&[
+                    request.method().as_str(),
+                    &route,
+                    &response.status().code.to_string(),
+                ])
+                .observe(start.elapsed().as_secs_f64());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Submission;
+
+    #[test]
+    fn render_marks_missing_captures() {
+        let text = render(&Snapshot::new());
+        assert!(
+            text.contains("cransubs_snapshot_age_seconds inf\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn render_reports_folders_and_captures() {
+        let mut snap = Snapshot::new();
+        snap.submissions = [("pretest", "foo"), ("pretest", "bar"), ("waiting", "baz")]
+            .into_iter()
+            .map(|(folder, pkg_name)| Submission::new(folder, pkg_name, "1.0", snap.capture_time))
+            .collect();
+        observe_snapshot(&snap);
+        capture_finished(true);
+        capture_finished(false);
+
+        let text = render(&snap);
+        for series in [
+            "cransubs_submissions{folder=\"pretest\"} 2\n",
+            "cransubs_submissions{folder=\"waiting\"} 1\n",
+            "cransubs_queue_size 3\n",
+            "cransubs_captures_total{result=\"success\"}",
+            "cransubs_captures_total{result=\"failure\"}",
+        ] {
+            assert!(text.contains(series), "{} missing from {}", series, text);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::version::PackageVersion;
+
+static SOURCE_EXTENSION: &str = ".tar.gz";
+
+lazy_static! {
+    // At least two characters, only ASCII letters, digits and dots, starting with a letter
+    // and not ending in a dot
+    static ref RE_PACKAGE_NAME: Regex = Regex::new(r"^[A-Za-z][A-Za-z0-9.]*[A-Za-z0-9]$").unwrap();
+}
+
+/// Name and version of a source package tarball, `<name>_<version>.tar.gz`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackageFile {
+    pub pkg_name: String,
+    pub pkg_version: String,
+}
+
+impl PackageFile {
+    /// Splits a file name following R's rules for package names and versions. Neither may
+    /// contain an underscore, so the first one separates them.
+    pub fn parse(file_name: &str) -> Result<PackageFile, String> {
+        let stem = file_name
+            .strip_suffix(SOURCE_EXTENSION)
+            .ok_or("Not a source package tarball")?;
+        let (pkg_name, pkg_version) = stem
+            .split_once('_')
+            .ok_or("Missing '_' between package name and version")?;
+
+        if !RE_PACKAGE_NAME.is_match(pkg_name) {
+            return Err(format!("Invalid package name '{}'", pkg_name));
+        }
+        pkg_version.parse::<PackageVersion>()?;
+
+        Ok(PackageFile {
+            pkg_name: pkg_name.to_owned(),
+            pkg_version: pkg_version.to_owned(),
+        })
+    }
+}
+
+/// A file in the queue that isn't a source package tarball.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UnparsedFile {
+    pub folder: String,
+    pub file_name: String,
+    pub file_bytes: usize,
+    /// Why the name was rejected
+    pub reason: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(file_name: &str) -> Option<(String, String)> {
+        PackageFile::parse(file_name)
+            .ok()
+            .map(|file| (file.pkg_name, file.pkg_version))
+    }
+
+    #[test]
+    fn parse_accepts_package_files() {
+        for (file_name, pkg_name, pkg_version) in [
+            ("foo_1.0.0.tar.gz", "foo", "1.0.0"),
+            ("bar_0.2-1.tar.gz", "bar", "0.2-1"),
+            ("data.table_1.14.8.tar.gz", "data.table", "1.14.8"),
+            ("R6_2.5.1.tar.gz", "R6", "2.5.1"),
+        ] {
+            assert_eq!(
+                parse(file_name),
+                Some((pkg_name.to_owned(), pkg_version.to_owned())),
+                "{}",
+                file_name
+            );
+        }
+    }
+
+    #[test]
+    fn parse_rejects_other_files() {
+        for file_name in [
+            "README",
+            "foo_1.0.tgz",
+            "foo_1.0.zip",
+            "foo.tar.gz",
+            "foo_bar_1.0.tar.gz",
+            "foo_1.0_R_x86_64-pc-linux-gnu.tar.gz",
+            "2foo_1.0.tar.gz",
+            "foo._1.0.tar.gz",
+            "f_1.0.tar.gz",
+            "foo_1.tar.gz",
+            "foo_1.0a.tar.gz",
+            "foo_1..0.tar.gz",
+        ] {
+            assert_eq!(parse(file_name), None, "{}", file_name);
+        }
+    }
+}
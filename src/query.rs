@@ -0,0 +1,340 @@
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use std::cmp::Ordering;
+
+use rocket::FromForm;
+
+use crate::snapshot::Submission;
+use crate::version;
+
+/// Query parameters accepted by `/snap`.
+///
+/// `folder` may be repeated, `sort` names a submission field and is descending
+/// when prefixed with `-`, e.g. `/snap?folder=pretest&name_prefix=data&sort=-file_time`.
+/// Folders sort in processing order, versions the way R orders them, other enumerations in
+/// the order they are documented and missing values first.
+#[derive(Debug, Default, FromForm)]
+pub struct SnapQuery<'r> {
+    folder: Vec<&'r str>,
+    name: Option<&'r str>,
+    name_prefix: Option<&'r str>,
+    name_regex: Option<&'r str>,
+    min_file_time: Option<&'r str>,
+    max_file_time: Option<&'r str>,
+    min_file_bytes: Option<usize>,
+    max_file_bytes: Option<usize>,
+    sort: Option<&'r str>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum SortKey {
+    RequestTime,
+    Folder,
+    FileTime,
+    FileBytes,
+    PkgName,
+    PkgVersion,
+    FileTimeSource,
+    QueuePosition,
+    EstimatedWaitSeconds,
+    Attempt,
+    Resubmission,
+    SupersededBy,
+    CranVersion,
+    Release,
+    VersionDelta,
+    VersionIsNewerThanCran,
+}
+
+fn compare_versions(a: &Option<String>, b: &Option<String>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => version::compare(a, b),
+        _ => a.is_some().cmp(&b.is_some()),
+    }
+}
+
+impl SortKey {
+    fn parse(field: &str) -> Option<SortKey> {
+        match field {
+            "request_time" => Some(SortKey::RequestTime),
+            "folder" => Some(SortKey::Folder),
+            "file_time" => Some(SortKey::FileTime),
+            "file_bytes" => Some(SortKey::FileBytes),
+            "pkg_name" => Some(SortKey::PkgName),
+            "pkg_version" => Some(SortKey::PkgVersion),
+            "file_time_source" => Some(SortKey::FileTimeSource),
+            "queue_position" => Some(SortKey::QueuePosition),
+            "estimated_wait_seconds" => Some(SortKey::EstimatedWaitSeconds),
+            "attempt" => Some(SortKey::Attempt),
+            "resubmission" => Some(SortKey::Resubmission),
+            "superseded_by" => Some(SortKey::SupersededBy),
+            "cran_version" => Some(SortKey::CranVersion),
+            "release" => Some(SortKey::Release),
+            "version_delta" => Some(SortKey::VersionDelta),
+            "version_is_newer_than_cran" => Some(SortKey::VersionIsNewerThanCran),
+            _ => None,
+        }
+    }
+
+    fn compare(self, a: &Submission, b: &Submission) -> Ordering {
+        match self {
+            SortKey::RequestTime => a.request_time.cmp(&b.request_time),
+            SortKey::Folder => {
+                (&a.folder_info.folder, &a.folder).cmp(&(&b.folder_info.folder, &b.folder))
+            }
+            SortKey::FileTime => a.file_time.cmp(&b.file_time),
+            SortKey::FileBytes => a.file_bytes.cmp(&b.file_bytes),
+            SortKey::PkgName => a.pkg_name.cmp(&b.pkg_name),
+            SortKey::PkgVersion => version::compare(&a.pkg_version, &b.pkg_version),
+            SortKey::FileTimeSource => a.file_time_source.cmp(&b.file_time_source),
+            SortKey::QueuePosition => a.queue_position.cmp(&b.queue_position),
+            SortKey::EstimatedWaitSeconds => {
+                a.estimated_wait_seconds.cmp(&b.estimated_wait_seconds)
+            }
+            SortKey::Attempt => a.attempt.cmp(&b.attempt),
+            SortKey::Resubmission => a.resubmission.cmp(&b.resubmission),
+            SortKey::SupersededBy => compare_versions(&a.superseded_by, &b.superseded_by),
+            SortKey::CranVersion => compare_versions(&a.cran_version, &b.cran_version),
+            SortKey::Release => a.release.cmp(&b.release),
+            SortKey::VersionDelta => a.version_delta.cmp(&b.version_delta),
+            SortKey::VersionIsNewerThanCran => a
+                .version_is_newer_than_cran
+                .cmp(&b.version_is_newer_than_cran),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Filter {
+    folders: Vec<String>,
+    name: Option<String>,
+    name_prefix: Option<String>,
+    name_regex: Option<Regex>,
+    min_file_time: Option<DateTime<Utc>>,
+    max_file_time: Option<DateTime<Utc>>,
+    min_file_bytes: Option<usize>,
+    max_file_bytes: Option<usize>,
+    sort: Option<(SortKey, bool)>,
+}
+
+fn parse_time(param: &str, value: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
+    value
+        .map(|v| {
+            DateTime::parse_from_rfc3339(v)
+                .map(|t| t.with_timezone(&Utc))
+                .map_err(|err| format!("Invalid {} '{}': {}", param, v, err))
+        })
+        .transpose()
+}
+
+impl SnapQuery<'_> {
+    pub fn compile(&self) -> Result<Filter, String> {
+        let name_regex = self
+            .name_regex
+            .map(|re| Regex::new(re).map_err(|err| format!("Invalid name_regex: {}", err)))
+            .transpose()?;
+
+        let sort = self
+            .sort
+            .map(|field| {
+                let (field, descending) = match field.strip_prefix('-') {
+                    Some(field) => (field, true),
+                    None => (field, false),
+                };
+                SortKey::parse(field)
+                    .map(|key| (key, descending))
+                    .ok_or_else(|| format!("Unknown sort field '{}'", field))
+            })
+            .transpose()?;
+
+        Ok(Filter {
+            folders: self.folder.iter().map(|f| f.to_string()).collect(),
+            name: self.name.map(str::to_owned),
+            name_prefix: self.name_prefix.map(str::to_owned),
+            name_regex,
+            min_file_time: parse_time("min_file_time", self.min_file_time)?,
+            max_file_time: parse_time("max_file_time", self.max_file_time)?,
+            min_file_bytes: self.min_file_bytes,
+            max_file_bytes: self.max_file_bytes,
+            sort,
+        })
+    }
+}
+
+impl Filter {
+    fn matches(&self, sub: &Submission) -> bool {
+        (self.folders.is_empty() || self.folders.contains(&sub.folder))
+            && self.name.as_ref().is_none_or(|name| sub.pkg_name == *name)
+            && self
+                .name_prefix
+                .as_ref()
+                .is_none_or(|prefix| sub.pkg_name.starts_with(prefix.as_str()))
+            && self
+                .name_regex
+                .as_ref()
+                .is_none_or(|re| re.is_match(&sub.pkg_name))
+            && self.min_file_time.is_none_or(|t| sub.file_time.is_some_and(|ft| ft >= t))
+            && self.max_file_time.is_none_or(|t| sub.file_time.is_some_and(|ft| ft <= t))
+            && self.min_file_bytes.is_none_or(|b| sub.file_bytes >= b)
+            && self.max_file_bytes.is_none_or(|b| sub.file_bytes <= b)
+    }
+
+    pub fn apply(&self, submissions: &mut Vec<Submission>) {
+        submissions.retain(|sub| self.matches(sub));
+
+        if let Some((key, descending)) = self.sort {
+            submissions.sort_by(|a, b| {
+                let ord = key.compare(a, b);
+                if descending {
+                    ord.reverse()
+                } else {
+                    ord
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::FileTimeSource;
+    use chrono::TimeZone;
+
+    fn submissions() -> Vec<Submission> {
+        let time = |hour| Utc.with_ymd_and_hms(2022, 10, 25, hour, 0, 0).unwrap();
+        let mut unknown_time = Submission::new("pretest", "datasets2", "1.0", time(11));
+        unknown_time.file_time = None;
+        unknown_time.file_time_source = FileTimeSource::Fallback;
+        unknown_time.queue_position = Some(2);
+        unknown_time.cran_version = Some("1.10".to_owned());
+        vec![
+            Submission {
+                file_bytes: 512,
+                queue_position: Some(1),
+                ..Submission::new("pretest", "data.table", "1.10", time(10))
+            },
+            Submission {
+                file_bytes: 4096,
+                queue_position: Some(3),
+                cran_version: Some("1.8".to_owned()),
+                ..Submission::new("waiting", "foo", "1.9", time(12))
+            },
+            Submission::new("inspect", "datasets", "0.2-1", time(11)),
+            unknown_time,
+        ]
+    }
+
+    fn apply(query: SnapQuery) -> Vec<String> {
+        let mut subs = submissions();
+        query.compile().unwrap().apply(&mut subs);
+        subs.into_iter().map(|sub| sub.pkg_name).collect()
+    }
+
+    #[test]
+    fn filter_matches_names() {
+        let exact = SnapQuery {
+            name: Some("datasets"),
+            ..SnapQuery::default()
+        };
+        assert_eq!(apply(exact), vec!["datasets"]);
+
+        let prefix = SnapQuery {
+            name_prefix: Some("data"),
+            ..SnapQuery::default()
+        };
+        assert_eq!(apply(prefix), vec!["data.table", "datasets", "datasets2"]);
+
+        let regex = SnapQuery {
+            name_regex: Some(r"^\w+\d$"),
+            folder: vec!["pretest", "waiting"],
+            ..SnapQuery::default()
+        };
+        assert_eq!(apply(regex), vec!["datasets2"]);
+    }
+
+    #[test]
+    fn filter_applies_bounds() {
+        // Submissions without a file time never fall within time bounds
+        let time = SnapQuery {
+            min_file_time: Some("2022-10-25T11:00:00Z"),
+            max_file_time: Some("2022-10-25T12:00:00+00:00"),
+            ..SnapQuery::default()
+        };
+        assert_eq!(apply(time), vec!["foo", "datasets"]);
+
+        let bytes = SnapQuery {
+            min_file_bytes: Some(1024),
+            max_file_bytes: Some(1024),
+            ..SnapQuery::default()
+        };
+        assert_eq!(apply(bytes), vec!["datasets", "datasets2"]);
+    }
+
+    #[test]
+    fn filter_sorts() {
+        let by_version = SnapQuery {
+            sort: Some("-pkg_version"),
+            ..SnapQuery::default()
+        };
+        assert_eq!(
+            apply(by_version),
+            vec!["data.table", "foo", "datasets2", "datasets"]
+        );
+
+        let by_folder = SnapQuery {
+            sort: Some("folder"),
+            ..SnapQuery::default()
+        };
+        assert_eq!(
+            apply(by_folder),
+            vec!["data.table", "datasets2", "datasets", "foo"]
+        );
+
+        let by_source = SnapQuery {
+            sort: Some("-file_time_source"),
+            ..SnapQuery::default()
+        };
+        assert_eq!(apply(by_source)[0], "datasets2");
+
+        // Submissions without a position come first
+        let by_position = SnapQuery {
+            sort: Some("queue_position"),
+            ..SnapQuery::default()
+        };
+        assert_eq!(
+            apply(by_position),
+            vec!["datasets", "data.table", "datasets2", "foo"]
+        );
+
+        let by_cran_version = SnapQuery {
+            sort: Some("-cran_version"),
+            ..SnapQuery::default()
+        };
+        assert_eq!(apply(by_cran_version)[..2], ["datasets2", "foo"]);
+    }
+
+    #[test]
+    fn compile_rejects_bad_parameters() {
+        for query in [
+            SnapQuery {
+                name_regex: Some("("),
+                ..SnapQuery::default()
+            },
+            SnapQuery {
+                min_file_time: Some("2022-10-25"),
+                ..SnapQuery::default()
+            },
+            SnapQuery {
+                max_file_time: Some("yesterday"),
+                ..SnapQuery::default()
+            },
+            SnapQuery {
+                sort: Some("-size"),
+                ..SnapQuery::default()
+            },
+        ] {
+            assert!(query.compile().is_err(), "{:?}", query);
+        }
+    }
+}
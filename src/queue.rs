@@ -0,0 +1,126 @@
+use chrono::Duration;
+use std::collections::HashMap;
+use tracing::error;
+
+use crate::history::History;
+use crate::snapshot::Snapshot;
+
+/// How far back departures count towards a folder's throughput.
+static THROUGHPUT_WINDOW_DAYS: i64 = 7;
+
+/// Submissions that left each folder within an observation window.
+#[derive(Clone, Debug)]
+pub struct Throughput {
+    pub departures: HashMap<String, usize>,
+    pub window: Duration,
+}
+
+impl Default for Throughput {
+    fn default() -> Throughput {
+        Throughput {
+            departures: HashMap::new(),
+            window: Duration::zero(),
+        }
+    }
+}
+
+impl Throughput {
+    /// Seconds until `position` submissions have left `folder` at the observed rate.
+    fn wait_seconds(&self, folder: &str, position: usize) -> Option<i64> {
+        let departures = *self.departures.get(folder)?;
+        if departures == 0 || self.window <= Duration::zero() {
+            return None;
+        }
+        Some(self.window.num_seconds() * position as i64 / departures as i64)
+    }
+}
+
+/// Numbers each submission within its folder, oldest `file_time` first, and estimates how
+/// long it waits until it leaves the folder.
+///
+/// CRAN doesn't promise first in, first out, so the estimate assumes it on average only.
+/// Submissions without a `file_time` can't be placed and get neither.
+pub fn annotate(snap: &mut Snapshot, throughput: &Throughput) {
+    let mut folders: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, sub) in snap.submissions.iter().enumerate() {
+        if sub.file_time.is_some() {
+            folders.entry(&sub.folder).or_default().push(i);
+        }
+    }
+
+    let mut positions: Vec<Option<usize>> = vec![None; snap.submissions.len()];
+    for queue in folders.values_mut() {
+        queue.sort_by_key(|&i| (snap.submissions[i].file_time, &snap.submissions[i].pkg_name));
+        for (position, &i) in queue.iter().enumerate() {
+            positions[i] = Some(position + 1);
+        }
+    }
+
+    for (sub, position) in snap.submissions.iter_mut().zip(positions) {
+        sub.queue_position = position;
+        sub.estimated_wait_seconds =
+            position.and_then(|position| throughput.wait_seconds(&sub.folder, position));
+    }
+}
+
+/// Like `annotate`, with the throughput recorded in `history` up to the capture.
+pub fn estimate(snap: &mut Snapshot, history: &History) {
+    let window = Duration::days(THROUGHPUT_WINDOW_DAYS);
+    let throughput = history
+        .throughput(window, snap.capture_time)
+        .unwrap_or_else(|err| {
+            error!(error = %err, "could not load folder throughput");
+            Throughput::default()
+        });
+    annotate(snap, &throughput);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::{FileTimeSource, Submission};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn annotate_orders_folders_by_file_time() {
+        let time = |hour| Utc.with_ymd_and_hms(2022, 10, 25, hour, 0, 0).unwrap();
+        let mut snap = Snapshot::new();
+        snap.submissions = vec![
+            Submission::new("pretest", "foo", "1.0", time(12)),
+            Submission::new("pretest", "bar", "1.0", time(9)),
+            Submission::new("waiting", "baz", "1.0", time(15)),
+            Submission {
+                file_time: None,
+                file_time_source: FileTimeSource::Fallback,
+                ..Submission::new("pretest", "qux", "1.0", time(23))
+            },
+        ];
+        let throughput = Throughput {
+            departures: HashMap::from([("pretest".to_owned(), 4)]),
+            window: Duration::hours(8),
+        };
+
+        annotate(&mut snap, &throughput);
+
+        let annotations: Vec<_> = snap
+            .submissions
+            .iter()
+            .map(|sub| {
+                (
+                    sub.pkg_name.as_str(),
+                    sub.queue_position,
+                    sub.estimated_wait_seconds,
+                )
+            })
+            .collect();
+        assert_eq!(
+            annotations,
+            vec![
+                ("foo", Some(2), Some(4 * 3600)),
+                ("bar", Some(1), Some(2 * 3600)),
+                ("baz", Some(1), None),
+                ("qux", None, None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::{
+        self,
+        sync::RwLock,
+        task,
+        time::{sleep, timeout},
+    },
+    Orbit, Rocket,
+};
+
+use crate::{
+    config::Settings,
+    cran::{self, CranIndexLoader},
+    events::Events,
+    history::History,
+    metrics, queue, resubmission,
+    snapshot::{CaptureError, Snapshot},
+    SnapshotContainer,
+};
+
+static RESTART_DELAY_SECONDS: u64 = 30;
+
+/// Tells a running capture to stop once the future waiting for it is gone,
+/// e.g. after the deadline passed or the server shuts down.
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Captures a new snapshot every refresh interval (plus some jitter) in a background task,
+/// so requests never wait for the FTP crawl.
+#[derive(Clone)]
+pub struct Refresher {
+    data: Arc<RwLock<SnapshotContainer>>,
+    history: Arc<History>,
+    events: Events,
+    settings: Arc<Settings>,
+    cran: Arc<CranIndexLoader>,
+    last_capture: Option<DateTime<Utc>>,
+}
+
+impl Refresher {
+    pub fn new(
+        data: Arc<RwLock<SnapshotContainer>>,
+        history: Arc<History>,
+        events: Events,
+        settings: Arc<Settings>,
+        last_capture: Option<DateTime<Utc>>,
+    ) -> Refresher {
+        let cran = Arc::new(CranIndexLoader::new(
+            &settings.cran_index,
+            settings.cran_index_max_age(),
+        ));
+
+        Refresher {
+            data,
+            history,
+            events,
+            settings,
+            cran,
+            last_capture,
+        }
+    }
+
+    fn next_delay(&self) -> Duration {
+        let jitter_ms =
+            rand::thread_rng().gen_range(0..=self.settings.refresh_jitter().as_millis() as u64);
+        self.settings.refresh_interval() + Duration::from_millis(jitter_ms)
+    }
+
+    async fn refresh(&self) {
+        info!("refreshing snapshot");
+        let cancel = Arc::new(AtomicBool::new(false));
+        let _guard = CancelOnDrop(cancel.clone());
+
+        let settings = self.settings.clone();
+        let deadline = settings.capture_deadline();
+        let span = Span::current();
+        let capture =
+            task::spawn_blocking(move || span.in_scope(|| Snapshot::capture(&settings, &cancel)));
+        let captured = match timeout(deadline, capture).await {
+            Ok(Ok(res)) => res.map_err(|err| err.to_string()),
+            Ok(Err(err)) => Err(format!("Capture task failed: {}", err)),
+            Err(_) => Err(format!(
+                "Capture exceeded deadline of {}s",
+                deadline.as_secs()
+            )),
+        };
+
+        match captured {
+            Ok(mut snap) => {
+                snap.keep_file_times(&self.data.read().await.snapshot);
+                metrics::capture_finished(true);
+                metrics::observe_snapshot(&snap);
+                let span = Span::current();
+                let prune_before = self.settings.history_retention().and_then(|retention| {
+                    snap.capture_time
+                        .checked_sub_signed(chrono::Duration::from_std(retention).ok()?)
+                });
+                let annotated = self
+                    .history
+                    .blocking(move |history| {
+                        span.in_scope(|| {
+                            let mut snap = snap;
+                            if let Err(err) = history.record(&snap) {
+                                error!(error = %err, "could not store snapshot");
+                            }
+                            if let Some(before) = prune_before {
+                                match history.prune(before) {
+                                    Ok(pruned) => debug!(pruned, "pruned old submissions"),
+                                    Err(err) => error!(error = %err, "could not prune history"),
+                                }
+                            }
+                            queue::estimate(&mut snap, history);
+                            resubmission::detect(&mut snap, history);
+                            Ok(snap)
+                        })
+                    })
+                    .await;
+                let snap = match annotated {
+                    Ok(snap) => snap,
+                    Err(err) => {
+                        error!(error = %err, "could not store snapshot");
+                        return;
+                    }
+                };
+                let mut snap = if self.settings.enrich_descriptions {
+                    self.enrich(snap).await
+                } else {
+                    snap
+                };
+                if let Some(index) = self.cran.index().await {
+                    cran::annotate(&mut snap, &index);
+                }
+                let mut data = self.data.write().await;
+                self.events.snapshot_captured(&data.snapshot, &snap);
+                data.snapshot = snap;
+            }
+            Err(err) => {
+                metrics::capture_finished(false);
+                error!(error = %err, "could not create snapshot");
+                self.events.capture_failed(err);
+            }
+        }
+    }
+
+    /// Reads descriptions within a deadline of their own. Those read before it passes are
+    /// cached, so a snapshot that couldn't be enriched in time is served without them and
+    /// the next capture continues.
+    async fn enrich(&self, snap: Snapshot) -> Snapshot {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let _guard = CancelOnDrop(cancel.clone());
+
+        let settings = self.settings.clone();
+        let history = self.history.clone();
+        let deadline = settings.capture_deadline();
+        let span = Span::current();
+        let mut enriched = snap.clone();
+        let enrich = task::spawn_blocking(move || {
+            span.in_scope(|| enriched.enrich(&settings, &history, &cancel))?;
+            Ok::<_, CaptureError>(enriched)
+        });
+
+        match timeout(deadline, enrich).await {
+            Ok(Ok(Ok(enriched))) => enriched,
+            Ok(Ok(Err(err))) => {
+                warn!(error = %err, "could not read descriptions");
+                snap
+            }
+            Ok(Err(err)) => {
+                error!(error = %err, "description task failed");
+                snap
+            }
+            Err(_) => {
+                warn!(
+                    deadline = deadline.as_secs(),
+                    "reading descriptions exceeded deadline"
+                );
+                snap
+            }
+        }
+    }
+
+    async fn run(self) {
+        // The stored snapshot predates the index
+        if let Some(index) = self.cran.index().await {
+            cran::annotate(&mut self.data.write().await.snapshot, &index);
+        }
+
+        // Don't recrawl right after a restart if the stored snapshot is still fresh
+        let mut delay = self
+            .last_capture
+            .and_then(|t| {
+                (t + self.settings.refresh_interval())
+                    .signed_duration_since(Utc::now())
+                    .to_std()
+                    .ok()
+            })
+            .unwrap_or_default();
+
+        loop {
+            self.data.write().await.next_update = Some(Utc::now() + delay);
+            sleep(delay).await;
+            self.refresh().instrument(info_span!("refresh")).await;
+            delay = self.next_delay();
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Refresher {
+    fn info(&self) -> Info {
+        Info {
+            name: "Refresh snapshots in the background",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let refresher = self.clone();
+        let mut shutdown = rocket.shutdown();
+
+        tokio::spawn(async move {
+            // Restart the refresh loop if it ever panics
+            loop {
+                let mut worker = tokio::spawn(refresher.clone().run());
+                tokio::select! {
+                    res = &mut worker => match res {
+                        Ok(()) => return,
+                        Err(err) => error!(error = %err, "refresh task failed, restarting"),
+                    },
+                    _ = &mut shutdown => {
+                        worker.abort();
+                        return;
+                    }
+                }
+                sleep(Duration::from_secs(RESTART_DELAY_SECONDS)).await;
+            }
+        });
+    }
+}
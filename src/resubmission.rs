@@ -0,0 +1,112 @@
+use std::collections::{BTreeMap, HashMap};
+use tracing::error;
+
+use crate::history::History;
+use crate::lifecycle::PackageKey;
+use crate::snapshot::{Snapshot, Submission};
+use crate::version;
+
+/// Flags repeated submissions of a package.
+///
+/// A version that entered the queue before is a resubmission, as is the highest version of
+/// a package while lower ones are still queued. It supersedes those, a tie between versions
+/// R considers equal, e.g. `1.0-1` and `1.0.1`, going to the one that arrived last.
+pub fn annotate(snap: &mut Snapshot, attempts: &HashMap<PackageKey, usize>) {
+    // Arrival of each version, its oldest file in the queue
+    let mut packages: BTreeMap<&str, BTreeMap<&str, Option<_>>> = BTreeMap::new();
+    for sub in &snap.submissions {
+        let arrived = packages
+            .entry(&sub.pkg_name)
+            .or_default()
+            .entry(&sub.pkg_version)
+            .or_insert(sub.file_time);
+        *arrived = (*arrived).min(sub.file_time);
+    }
+
+    let newest: HashMap<&str, (&str, Vec<String>)> = packages
+        .iter()
+        .filter(|(_, versions)| versions.len() > 1)
+        .filter_map(|(&pkg_name, versions)| {
+            let (&newest, _) = versions.iter().max_by(|a, b| {
+                version::compare(a.0, b.0).then_with(|| (a.1, a.0).cmp(&(b.1, b.0)))
+            })?;
+            let mut older: Vec<String> = versions
+                .keys()
+                .filter(|&&version| version != newest)
+                .map(|&version| version.to_owned())
+                .collect();
+            older.sort_by(|a, b| version::compare(a, b));
+            Some((pkg_name, (newest, older)))
+        })
+        .collect();
+
+    let annotations: Vec<(Option<String>, Vec<String>)> = snap
+        .submissions
+        .iter()
+        .map(|sub| match newest.get(sub.pkg_name.as_str()) {
+            Some((newest, older)) if *newest == sub.pkg_version => (None, older.clone()),
+            Some((newest, _)) => (Some((*newest).to_owned()), Vec::new()),
+            None => (None, Vec::new()),
+        })
+        .collect();
+
+    for (sub, (superseded_by, supersedes)) in snap.submissions.iter_mut().zip(annotations) {
+        sub.attempt = attempts.get(&sub.package()).copied().unwrap_or(1);
+        sub.resubmission = sub.attempt > 1 || !supersedes.is_empty();
+        sub.superseded_by = superseded_by;
+        sub.supersedes = supersedes;
+    }
+}
+
+/// Like `annotate`, with the attempts recorded in `history`.
+pub fn detect(snap: &mut Snapshot, history: &History) {
+    let packages: Vec<PackageKey> = snap.submissions.iter().map(Submission::package).collect();
+    let attempts = history.attempts(&packages).unwrap_or_else(|err| {
+        error!(error = %err, "could not load submission attempts");
+        HashMap::new()
+    });
+    annotate(snap, &attempts);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn annotate_flags_resubmissions() {
+        let time = |hour| Utc.with_ymd_and_hms(2022, 10, 25, hour, 0, 0).unwrap();
+        let mut snap = Snapshot::new();
+        snap.submissions = vec![
+            Submission::new("waiting", "foo", "1.9", time(12)),
+            Submission::new("pretest", "foo", "1.10", time(9)),
+            Submission::new("pretest", "bar", "0.2", time(10)),
+            Submission::new("pretest", "baz", "2.0", time(11)),
+        ];
+        let attempts = HashMap::from([(snap.submissions[2].package(), 3)]);
+
+        annotate(&mut snap, &attempts);
+
+        let flags: Vec<_> = snap
+            .submissions
+            .iter()
+            .map(|sub| {
+                (
+                    sub.attempt,
+                    sub.resubmission,
+                    sub.superseded_by.as_deref(),
+                    sub.supersedes.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            flags,
+            vec![
+                (1, false, Some("1.10"), vec![]),
+                (1, true, None, vec!["1.9".to_owned()]),
+                (3, true, None, vec![]),
+                (1, false, None, vec![]),
+            ]
+        );
+    }
+}
@@ -1,124 +1,333 @@
-use chrono::{DateTime, SubsecRound, Utc};
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDateTime, Offset, SubsecRound, Utc};
 use chrono_tz::Europe::Vienna;
 use chrono_tz::Tz;
-use lazy_static::lazy_static;
-use regex::Regex;
-use std::{error, str::FromStr};
+use std::{
+    collections::HashMap,
+    error,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use suppaftp::list::File;
-use suppaftp::FtpStream;
+use tracing::{debug, debug_span, info, info_span, warn};
 
 use rocket::serde::{Deserialize, Serialize};
 
-static CRAN_HOST: &'static str = "cran.r-project.org:21";
-static CRAN_ROOT: &'static str = "/incoming";
-static CRAN_USER: &'static str = "anonymous";
-static CRAN_PASSWORD: &'static str = "anonymous";
+use crate::config::Settings;
+use crate::cran::Release;
+use crate::description::{self, Description};
+use crate::folder::FolderInfo;
+use crate::history::History;
+use crate::lifecycle::PackageKey;
+use crate::package_file::{PackageFile, UnparsedFile};
+use crate::source::{FtpSource, SubmissionSource};
+use crate::version::VersionDelta;
 
-lazy_static! {
-    static ref RE_PACKAGE_FILE: Regex = Regex::new(r"^(.+)_(.+)\.tar\.gz$").unwrap();
+pub type CaptureError = Box<dyn error::Error + Send + Sync>;
+
+/// Where a submission's `file_time` came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum FileTimeSource {
+    /// Second precision modification time from `MDTM`
+    Mdtm,
+    /// Minute precision time from the directory listing, used when `MDTM` failed
+    List,
+    /// Neither gave a trustworthy time, `file_time` is empty
+    Fallback,
+}
+
+impl FileTimeSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileTimeSource::Mdtm => "mdtm",
+            FileTimeSource::List => "list",
+            FileTimeSource::Fallback => "fallback",
+        }
+    }
+}
+
+impl FromStr for FileTimeSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FileTimeSource, String> {
+        match s {
+            "mdtm" => Ok(FileTimeSource::Mdtm),
+            "list" => Ok(FileTimeSource::List),
+            "fallback" => Ok(FileTimeSource::Fallback),
+            _ => Err(format!("Unknown file time source '{}'", s)),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct Submission {
-    request_time: DateTime<Utc>,
-    folder: String,
+    pub request_time: DateTime<Utc>,
+    pub folder: String,
+    pub folder_info: FolderInfo,
     //file_name: String,
-    file_time: DateTime<Utc>,
-    file_bytes: usize,
-    pkg_name: String,
-    pkg_version: String,
+    pub file_time: Option<DateTime<Utc>>,
+    pub file_time_source: FileTimeSource,
+    pub file_bytes: usize,
+    pub pkg_name: String,
+    pub pkg_version: String,
+    /// Position within the folder, 1 being the oldest file
+    pub queue_position: Option<usize>,
+    /// Estimated seconds from `capture_time` until the submission leaves its folder
+    pub estimated_wait_seconds: Option<i64>,
+    /// How often this version entered the queue, counting the current stay
+    pub attempt: usize,
+    /// Entered the queue before, or replaces an older version still in it
+    pub resubmission: bool,
+    /// Newer version of the package that arrived while this one is queued
+    pub superseded_by: Option<String>,
+    /// Older versions of the package still in the queue
+    pub supersedes: Vec<String>,
+    /// Version published on CRAN, if any
+    pub cran_version: Option<String>,
+    /// Unknown while the CRAN index couldn't be loaded
+    pub release: Option<Release>,
+    /// Change from `cran_version`
+    pub version_delta: Option<VersionDelta>,
+    /// Whether `pkg_version` is higher than `cran_version`, by R's rules, unknown for new
+    /// packages
+    pub version_is_newer_than_cran: Option<bool>,
+    /// Metadata from the tarball's `DESCRIPTION`, if enrichment is enabled
+    pub description: Option<Description>,
+}
+
+impl Submission {
+    /// A submission as the crawl found it, before any annotation.
+    pub fn crawled(
+        folder: &str,
+        package: PackageKey,
+        request_time: DateTime<Utc>,
+        file_time: Option<DateTime<Utc>>,
+        file_time_source: FileTimeSource,
+        file_bytes: usize,
+    ) -> Submission {
+        Submission {
+            request_time,
+            folder: folder.to_owned(),
+            folder_info: FolderInfo::new(folder),
+            file_time,
+            file_time_source,
+            file_bytes,
+            pkg_name: package.pkg_name,
+            pkg_version: package.pkg_version,
+            queue_position: None,
+            estimated_wait_seconds: None,
+            attempt: 1,
+            resubmission: false,
+            superseded_by: None,
+            supersedes: Vec::new(),
+            cran_version: None,
+            release: None,
+            version_delta: None,
+            version_is_newer_than_cran: None,
+            description: None,
+        }
+    }
+
+    pub fn package(&self) -> PackageKey {
+        PackageKey {
+            pkg_name: self.pkg_name.clone(),
+            pkg_version: self.pkg_version.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Submission {
+    /// A 1 KiB submission requested and modified at `time`, without annotations.
+    pub fn new(folder: &str, pkg_name: &str, pkg_version: &str, time: DateTime<Utc>) -> Submission {
+        let package = PackageKey {
+            pkg_name: pkg_name.to_owned(),
+            pkg_version: pkg_version.to_owned(),
+        };
+        Submission::crawled(folder, package, time, Some(time), FileTimeSource::Mdtm, 1024)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct Snapshot {
-    capture_time: DateTime<Utc>,
-    capture_duration: i64,
-    submissions: Vec<Submission>,
+    pub capture_time: DateTime<Utc>,
+    /// `false` for the empty placeholder served until a capture succeeds
+    pub captured: bool,
+    pub capture_duration: i64,
+    pub submissions: Vec<Submission>,
+    /// Files that aren't source package tarballs. Not kept in the history.
+    pub unparsed_files: Vec<UnparsedFile>,
 }
 
 impl Snapshot {
     pub fn new() -> Snapshot {
         Snapshot {
             capture_time: Utc::now(),
+            captured: false,
             capture_duration: 0,
             submissions: Vec::new(),
+            unparsed_files: Vec::new(),
         }
     }
 
-    pub fn capture() -> Result<Snapshot, Box<dyn error::Error>> {
-        capture_snapshot()
+    /// Crawls the CRAN incoming folders. Blocks until done, so run it off the async runtime.
+    /// Setting `cancel` aborts the crawl at the next FTP command.
+    pub fn capture(settings: &Settings, cancel: &AtomicBool) -> Result<Snapshot, CaptureError> {
+        let mut source = FtpSource::connect(&settings.ftp_host, settings.ftp_port, &settings.ftp_user, &settings.ftp_password)?;
+        capture_snapshot(&mut source, &settings.ftp_root, settings.max_depth, cancel)
     }
-}
 
-fn create_entry(ftp_file: &File, folder: &str, request_time: &DateTime<Utc>, modified_time: &DateTime<Utc>) -> Option<Submission> {
-    if !ftp_file.is_file() {
-        return None;
-    }
-
-    match RE_PACKAGE_FILE.captures(ftp_file.name()) {
-        Some(caps) => {
-            Some(Submission {
-                request_time: request_time.to_owned(),
-                folder: folder[(CRAN_ROOT.len() + 1).min(folder.len())..].to_owned(),
-                //file_name: ftpfile_sub.name().to_owned(),
-                file_time: modified_time.clone(),
-                file_bytes: ftp_file.size(),
-                pkg_name: caps.get(1).map_or("[unknown]", |c| c.as_str()).to_owned(),
-                pkg_version: caps.get(2).map_or("[unknown]", |c| c.as_str()).to_owned(),
-            })
+    /// Takes over `file_time` from `previous` for files this capture couldn't date but an earlier
+    /// one could. A reading from the hour repeated when the clocks go back only resolves while
+    /// that hour lasts, so without this it would flip to `None` on the next capture.
+    pub fn keep_file_times(&mut self, previous: &Snapshot) {
+        let known: HashMap<_, _> = previous
+            .submissions
+            .iter()
+            .filter_map(|sub| Some((file_key(sub), (sub.file_time?, sub.file_time_source))))
+            .collect();
+        for sub in self.submissions.iter_mut().filter(|sub| sub.file_time.is_none()) {
+            if let Some(&(time, source)) = known.get(&file_key(sub)) {
+                sub.file_time = Some(time);
+                sub.file_time_source = source;
+            }
         }
-        None => None,
+    }
+
+    /// Attaches `DESCRIPTION` metadata over a connection of its own, so a slow download can't
+    /// cost the crawl, opened only if the cache lacks a version. Blocks like `capture`, setting
+    /// `cancel` stops before the next download.
+    pub fn enrich(&mut self, settings: &Settings, history: &History, cancel: &AtomicBool) -> Result<(), CaptureError> {
+        let connect = || FtpSource::connect(&settings.ftp_host, settings.ftp_port, &settings.ftp_user, &settings.ftp_password);
+        description::enrich(self, connect, &settings.ftp_root, history, cancel)
     }
 }
 
+// A file is taken to be unchanged while its folder, package version and size are
+fn file_key(sub: &Submission) -> (String, PackageKey, usize) {
+    (sub.folder.clone(), sub.package(), sub.file_bytes)
+}
 
+fn create_entry(ftp_file: &File, package: PackageFile, folder: &str, request_time: &DateTime<Utc>, file_time: Option<(DateTime<Utc>, FileTimeSource)>) -> Submission {
+    let package = PackageKey {
+        pkg_name: package.pkg_name,
+        pkg_version: package.pkg_version,
+    };
+    Submission::crawled(
+        folder,
+        package,
+        *request_time,
+        file_time.map(|(time, _)| time),
+        file_time.map_or(FileTimeSource::Fallback, |(_, source)| source),
+        ftp_file.size(),
+    )
+}
 
-fn capture_snapshot() -> Result<Snapshot, Box<dyn error::Error>> {
-    // create connection
-    let mut ftp_stream = FtpStream::connect(CRAN_HOST)?;
-    let _ = ftp_stream.login(CRAN_USER, CRAN_PASSWORD)?;
+// CRAN's FTP server reports times in Vienna local time instead of UTC. Files can't have been
+// modified after `not_after`, the time they were listed, which settles most readings from the
+// hour that happens twice when the clocks go back.
+fn vienna_to_utc(local_time: NaiveDateTime, not_after: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+    match local_time.and_local_timezone::<Tz>(Vienna) {
+        LocalResult::Single(t) => Some(t.with_timezone(&Utc)),
+        LocalResult::Ambiguous(earliest, latest) => {
+            let latest = latest.with_timezone(&Utc);
+            if latest > *not_after {
+                Some(earliest.with_timezone(&Utc))
+            } else {
+                warn!(%local_time, "ambiguous Vienna local time");
+                None
+            }
+        }
+        // When the clocks go forward only a clock that missed the switch still reads a time
+        // inside the skipped hour, so it is on the offset in effect before the gap
+        LocalResult::None => {
+            let before = (local_time - Duration::hours(1))
+                .and_local_timezone::<Tz>(Vienna)
+                .earliest()?;
+            let offset = Duration::seconds(before.offset().fix().local_minus_utc().into());
+            Some(DateTime::from_naive_utc_and_offset(local_time - offset, Utc))
+        }
+    }
+}
 
+// Listings show no year for recent files and suppaftp assumes the current one,
+// move them to the last year before `not_after` instead
+fn list_to_utc(ftp_file: &File, not_after: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let mut listed = DateTime::<Utc>::from(ftp_file.modified()).naive_utc();
+    if listed.year() == Utc::now().year() {
+        listed = listed.with_year(not_after.year())?;
+    }
+    match vienna_to_utc(listed, not_after) {
+        Some(t) if t > *not_after => vienna_to_utc(listed.with_year(listed.year() - 1)?, not_after),
+        t => t,
+    }
+}
+
+fn check_cancelled(cancel: &AtomicBool) -> Result<(), CaptureError> {
+    if cancel.load(Ordering::Relaxed) {
+        return Err("Capture cancelled".into());
+    }
+    Ok(())
+}
+
+fn capture_snapshot(source: &mut dyn SubmissionSource, root: &str, max_depth: u32, cancel: &AtomicBool) -> Result<Snapshot, CaptureError> {
     let capture_time = Utc::now();
+    let _span = info_span!("capture", root).entered();
 
     let mut snap = Snapshot {
         capture_time: capture_time.round_subsecs(0),
+        captured: true,
         capture_duration: 0,
         submissions: Vec::new(),
+        unparsed_files: Vec::new(),
     };
 
     // recursively traverse folders
 
-    let max_depth: u32 = 2;
-    let mut folder_stack: Vec<(u32, String)> = vec![(0, CRAN_ROOT.to_owned())];
+    let mut folder_stack: Vec<(u32, String)> = vec![(0, root.to_owned())];
 
     while let Some((depth, ftp_path)) = folder_stack.pop() {
-        //println!("Explore depth {}: '{}'", depth, ftp_path);
+        let _span = debug_span!("list", path = %ftp_path, depth).entered();
+        check_cancelled(cancel)?;
 
+        let folder = &ftp_path[(root.len() + 1).min(ftp_path.len())..];
         let request_time: DateTime<Utc> = Utc::now().round_subsecs(0);
-        for ftp_res in ftp_stream.list(Some(&ftp_path))? {
+        let entries = source.list(&ftp_path)?;
+        debug!(entries = entries.len(), "listed folder");
+        for ftp_res in entries {
             let ftp_file = File::from_str(&ftp_res)?;
             if ftp_file.is_directory() {
                 if depth < max_depth {
                     folder_stack.push((depth + 1, [&ftp_path, ftp_file.name()].join("/")));
                 }
             } else if ftp_file.is_file() {
-                let local_time_result = ftp_stream
-                    .mdtm([&ftp_path, ftp_file.name()].join("/"))
-                    .unwrap_or(Utc::now().naive_utc())
-                    .and_local_timezone::<Tz>(Vienna);
-
-                // Daylight savings time bugfix
-                let modified_time: DateTime<Utc> = match local_time_result {
-                    chrono::LocalResult::None => Utc::now(),
-                    chrono::LocalResult::Single(t) => t.with_timezone(&Utc),
-                    chrono::LocalResult::Ambiguous(t, _) => t.with_timezone(&Utc),
+                let package = match PackageFile::parse(ftp_file.name()) {
+                    Ok(package) => package,
+                    Err(reason) => {
+                        debug!(file_name = ftp_file.name(), %reason, "skipping file");
+                        snap.unparsed_files.push(UnparsedFile {
+                            folder: folder.to_owned(),
+                            file_name: ftp_file.name().to_owned(),
+                            file_bytes: ftp_file.size(),
+                            reason,
+                        });
+                        continue;
+                    }
                 };
-                    
-                if let Some(entry) = create_entry(&ftp_file, &ftp_path, &request_time, &modified_time) {
-                    snap.submissions.push(entry);
-                }
+
+                check_cancelled(cancel)?;
+                let file_path = [&ftp_path, ftp_file.name()].join("/");
+                let file_time = match source.mdtm(&file_path) {
+                    Ok(time) => vienna_to_utc(time, &request_time).map(|t| (t, FileTimeSource::Mdtm)),
+                    Err(err) => {
+                        warn!(path = %file_path, error = %err, "MDTM failed, using the listing");
+                        list_to_utc(&ftp_file, &request_time).map(|t| (t, FileTimeSource::List))
+                    }
+                };
+
+                snap.submissions.push(create_entry(&ftp_file, package, folder, &request_time, file_time));
             }
             // do nothing for symlinks
         }
@@ -127,6 +336,177 @@ fn capture_snapshot() -> Result<Snapshot, Box<dyn error::Error>> {
     snap.capture_duration = Utc::now()
         .signed_duration_since(capture_time)
         .num_milliseconds();
+    info!(
+        submissions = snap.submissions.len(),
+        duration_ms = snap.capture_duration,
+        "capture finished"
+    );
+
+    Ok(snap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FixtureSource;
+    use chrono::TimeZone;
+
+    static TRANSCRIPT: &str = include_str!("../tests/fixtures/incoming.transcript");
+    static MAX_DEPTH: u32 = 2;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    fn vienna(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+    }
+
+    fn capture(max_depth: u32) -> Snapshot {
+        let mut source = FixtureSource::parse(TRANSCRIPT);
+        capture_snapshot(&mut source, "/incoming", max_depth, &AtomicBool::new(false)).unwrap()
+    }
+
+    fn packages(snap: &Snapshot) -> Vec<(&str, &str)> {
+        let mut packages: Vec<_> = snap
+            .submissions
+            .iter()
+            .map(|sub| (sub.folder.as_str(), sub.pkg_name.as_str()))
+            .collect();
+        packages.sort();
+        packages
+    }
+
+    #[test]
+    fn create_entry_parses_package_files() {
+        let file = File::from_str("-rw-r--r--    1 ftp      ftp        104857 Oct 25 14:30 foo_1.0.0.tar.gz").unwrap();
+        let time = utc(2022, 10, 25, 12, 30);
+
+        let package = PackageFile::parse(file.name()).unwrap();
+
+        let entry = create_entry(&file, package.clone(), "pretest", &time, Some((time, FileTimeSource::Mdtm)));
+        assert_eq!(entry.folder, "pretest");
+        assert_eq!(entry.pkg_name, "foo");
+        assert_eq!(entry.pkg_version, "1.0.0");
+        assert_eq!(entry.file_bytes, 104857);
+        assert_eq!(entry.file_time, Some(time));
+        assert_eq!(entry.file_time_source, FileTimeSource::Mdtm);
+
+        let entry = create_entry(&file, package, "pretest", &time, None);
+        assert_eq!(entry.file_time, None);
+        assert_eq!(entry.file_time_source, FileTimeSource::Fallback);
+    }
+
+    #[test]
+    fn vienna_to_utc_applies_dst_offset() {
+        let later = utc(2023, 1, 1, 0, 0);
+        assert_eq!(vienna_to_utc(vienna(2022, 10, 25, 14, 30), &later), Some(utc(2022, 10, 25, 12, 30)));
+        assert_eq!(vienna_to_utc(vienna(2022, 12, 15, 9, 0), &later), Some(utc(2022, 12, 15, 8, 0)));
+    }
+
+    #[test]
+    fn vienna_to_utc_resolves_dst_transitions() {
+        // 02:30 happens twice when the clocks go back, at 00:30Z and at 01:30Z
+        let fold = vienna(2022, 10, 30, 2, 30);
+        assert_eq!(vienna_to_utc(fold, &utc(2022, 10, 30, 1, 0)), Some(utc(2022, 10, 30, 0, 30)));
+        assert_eq!(vienna_to_utc(fold, &utc(2022, 10, 30, 2, 0)), None);
 
-    return Ok(snap);
+        // 02:30 never happens when the clocks go forward
+        let gap = vienna(2022, 3, 27, 2, 30);
+        assert_eq!(vienna_to_utc(gap, &utc(2023, 1, 1, 0, 0)), Some(utc(2022, 3, 27, 1, 30)));
+    }
+
+    #[test]
+    fn keep_file_times_carries_over_resolved_times() {
+        let fold = utc(2022, 10, 30, 0, 30);
+        let mut previous = Snapshot::new();
+        previous.submissions = vec![Submission::new("pretest", "foo", "1.0.0", fold)];
+
+        // The next capture can't tell which 02:30 the file was modified at anymore
+        let mut snap = Snapshot::new();
+        let mut unresolved = Submission::new("pretest", "foo", "1.0.0", fold);
+        unresolved.file_time = None;
+        unresolved.file_time_source = FileTimeSource::Fallback;
+        let moved = Submission { folder: "inspect".to_owned(), ..unresolved.clone() };
+        snap.submissions = vec![unresolved, moved];
+
+        snap.keep_file_times(&previous);
+        assert_eq!(snap.submissions[0].file_time, Some(fold));
+        assert_eq!(snap.submissions[0].file_time_source, FileTimeSource::Mdtm);
+        // A copy in another folder is another file
+        assert_eq!(snap.submissions[1].file_time, None);
+    }
+
+    #[test]
+    fn list_to_utc_infers_the_year() {
+        let file = File::from_str("-rw-r--r--    1 ftp      ftp        104857 Oct 25 14:30 foo_1.0.0.tar.gz").unwrap();
+        assert_eq!(list_to_utc(&file, &utc(2022, 11, 1, 0, 0)), Some(utc(2022, 10, 25, 12, 30)));
+        assert_eq!(list_to_utc(&file, &utc(2023, 1, 10, 0, 0)), Some(utc(2022, 10, 25, 12, 30)));
+
+        let old = File::from_str("-rw-r--r--    1 ftp      ftp           312 Jan 12  2021 README").unwrap();
+        assert_eq!(list_to_utc(&old, &utc(2022, 11, 1, 0, 0)), Some(utc(2021, 1, 11, 23, 0)));
+    }
+
+    #[test]
+    fn capture_crawls_folders() {
+        // KH/waiting/deep is beyond MAX_DEPTH and missing from the transcript, listing it would fail
+        let snap = capture(MAX_DEPTH);
+
+        assert_eq!(
+            packages(&snap),
+            vec![
+                ("KH", "qux"),
+                ("KH/waiting", "quux"),
+                ("pretest", "bar"),
+                ("pretest", "foo"),
+                ("waiting", "baz"),
+                ("waiting", "corge"),
+            ]
+        );
+
+        let find = |name: &str| snap.submissions.iter().find(|sub| sub.pkg_name == name).unwrap();
+        let baz = find("baz");
+        assert_eq!(baz.pkg_version, "2.1");
+        assert_eq!(baz.file_bytes, 8192);
+        assert_eq!(baz.file_time, Some(utc(2022, 12, 15, 8, 0)));
+        assert_eq!(baz.file_time_source, FileTimeSource::Mdtm);
+
+        // MDTM failed, so the time comes from the listing
+        let corge = find("corge");
+        assert!(corge.file_time.is_some());
+        assert_eq!(corge.file_time_source, FileTimeSource::List);
+
+        // Listed years after the ambiguous hour, both readings are in the past
+        let bar = find("bar");
+        assert_eq!(bar.file_time, None);
+        assert_eq!(bar.file_time_source, FileTimeSource::Fallback);
+    }
+
+    #[test]
+    fn capture_reports_unparsed_files() {
+        let snap = capture(MAX_DEPTH);
+
+        let unparsed: Vec<_> = snap
+            .unparsed_files
+            .iter()
+            .map(|file| (file.folder.as_str(), file.file_name.as_str()))
+            .collect();
+        assert_eq!(unparsed, vec![("", "README"), ("pretest", "foo_1.0.0.tgz")]);
+        assert_eq!(snap.unparsed_files[1].file_bytes, 512);
+    }
+
+    #[test]
+    fn capture_respects_max_depth() {
+        assert_eq!(capture(1).submissions.len(), 5);
+        assert!(packages(&capture(0)).is_empty());
+    }
+
+    #[test]
+    fn capture_stops_when_cancelled() {
+        let mut source = FixtureSource::parse(TRANSCRIPT);
+        assert!(capture_snapshot(&mut source, "/incoming", MAX_DEPTH, &AtomicBool::new(true)).is_err());
+    }
 }
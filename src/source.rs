@@ -0,0 +1,187 @@
+use chrono::NaiveDateTime;
+use std::{net::ToSocketAddrs, time::Duration};
+use suppaftp::FtpStream;
+use tracing::warn;
+
+use crate::description;
+use crate::metrics;
+use crate::snapshot::CaptureError;
+
+static FTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where the crawler reads folder listings and modification times from.
+pub trait SubmissionSource {
+    /// Raw `LIST` lines of a folder.
+    fn list(&mut self, path: &str) -> Result<Vec<String>, CaptureError>;
+
+    /// `MDTM` of a file, in the server's local time.
+    fn mdtm(&mut self, path: &str) -> Result<NaiveDateTime, CaptureError>;
+
+    /// `DESCRIPTION` of the package tarball at `path`, `file_bytes` long, downloading no more
+    /// of it than needed.
+    fn description(
+        &mut self,
+        path: &str,
+        pkg_name: &str,
+        file_bytes: usize,
+    ) -> Result<Option<String>, CaptureError>;
+}
+
+pub struct FtpSource {
+    stream: FtpStream,
+}
+
+impl FtpSource {
+    pub fn connect(
+        host: &str,
+        port: u16,
+        user: &str,
+        password: &str,
+    ) -> Result<FtpSource, CaptureError> {
+        let addr = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("Could not resolve {}", host))?;
+
+        let mut stream = FtpStream::connect_timeout(addr, FTP_TIMEOUT)?;
+        stream.get_ref().set_read_timeout(Some(FTP_TIMEOUT))?;
+        stream.get_ref().set_write_timeout(Some(FTP_TIMEOUT))?;
+        metrics::ftp_command("USER");
+        stream.login(user, password)?;
+
+        Ok(FtpSource { stream })
+    }
+}
+
+impl SubmissionSource for FtpSource {
+    fn list(&mut self, path: &str) -> Result<Vec<String>, CaptureError> {
+        metrics::ftp_command("LIST");
+        Ok(self.stream.list(Some(path))?)
+    }
+
+    fn mdtm(&mut self, path: &str) -> Result<NaiveDateTime, CaptureError> {
+        metrics::ftp_command("MDTM");
+        Ok(self.stream.mdtm(path)?)
+    }
+
+    fn description(
+        &mut self,
+        path: &str,
+        pkg_name: &str,
+        file_bytes: usize,
+    ) -> Result<Option<String>, CaptureError> {
+        metrics::ftp_command("RETR");
+        let mut data = self.stream.retr_as_stream(path)?;
+        let dcf = description::extract(&mut data, pkg_name, file_bytes as u64);
+        // Stop the transfer rather than download the rest of the tarball, also after a failed
+        // read so the server's reply to it doesn't answer the next command. If it had already
+        // finished, some servers reject the abort, which doesn't affect what was read.
+        metrics::ftp_command("ABOR");
+        if let Err(err) = self.stream.abort(data) {
+            warn!(path, error = %err, "could not abort transfer");
+        }
+        Ok(dcf?)
+    }
+}
+
+impl Drop for FtpSource {
+    fn drop(&mut self) {
+        let _ = self.stream.quit();
+    }
+}
+
+/// Replays a recorded FTP session.
+///
+/// A transcript consists of commands (`> LIST <path>`, `> MDTM <path>`, `> RETR <path>`),
+/// each followed by the server's reply lines prefixed with `< `. For `LIST` these are the
+/// listing entries, for `MDTM` the status line, e.g. `< 213 20221025143000` or `< 550 Could
+/// not get file modification time.`, and for `RETR` the lines of the `DESCRIPTION` inside
+/// the tarball. Lines starting with `#` are comments.
+#[cfg(test)]
+pub struct FixtureSource {
+    lists: std::collections::HashMap<String, Vec<String>>,
+    mdtms: std::collections::HashMap<String, String>,
+    descriptions: std::collections::HashMap<String, Vec<String>>,
+}
+
+#[cfg(test)]
+impl FixtureSource {
+    pub fn parse(transcript: &str) -> FixtureSource {
+        let mut source = FixtureSource {
+            lists: Default::default(),
+            mdtms: Default::default(),
+            descriptions: Default::default(),
+        };
+        let mut command: Option<(&str, &str)> = None;
+
+        for line in transcript.lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(cmd) = line.strip_prefix("> ") {
+                let (verb, path) = cmd.split_once(' ').unwrap_or((cmd, ""));
+                if verb == "LIST" {
+                    source.lists.entry(path.to_owned()).or_default();
+                }
+                command = Some((verb, path));
+            } else if let Some(reply) = line.strip_prefix("< ") {
+                match command {
+                    Some(("LIST", path)) => source
+                        .lists
+                        .entry(path.to_owned())
+                        .or_default()
+                        .push(reply.to_owned()),
+                    Some(("MDTM", path)) => {
+                        source.mdtms.insert(path.to_owned(), reply.to_owned());
+                    }
+                    Some(("RETR", path)) => source
+                        .descriptions
+                        .entry(path.to_owned())
+                        .or_default()
+                        .push(reply.to_owned()),
+                    _ => panic!("Reply without command: {}", line),
+                }
+            } else {
+                panic!("Malformed transcript line: {}", line);
+            }
+        }
+
+        source
+    }
+}
+
+#[cfg(test)]
+impl SubmissionSource for FixtureSource {
+    fn list(&mut self, path: &str) -> Result<Vec<String>, CaptureError> {
+        self.lists
+            .get(path)
+            .cloned()
+            .ok_or_else(|| format!("550 {}: No such file or directory", path).into())
+    }
+
+    fn mdtm(&mut self, path: &str) -> Result<NaiveDateTime, CaptureError> {
+        let reply = self
+            .mdtms
+            .get(path)
+            .ok_or_else(|| format!("550 {}: No such file or directory", path))?;
+
+        match reply.split_once(' ') {
+            Some(("213", time)) => Ok(NaiveDateTime::parse_from_str(time, "%Y%m%d%H%M%S")?),
+            _ => Err(reply.clone().into()),
+        }
+    }
+
+    fn description(
+        &mut self,
+        path: &str,
+        _pkg_name: &str,
+        _file_bytes: usize,
+    ) -> Result<Option<String>, CaptureError> {
+        let lines = self
+            .descriptions
+            .get(path)
+            .ok_or_else(|| format!("550 {}: No such file or directory", path))?;
+        Ok(Some(lines.join("\n")))
+    }
+}
@@ -0,0 +1,248 @@
+use chrono::{DateTime, Datelike, NaiveDate, Timelike, Utc, Weekday};
+use std::collections::BTreeMap;
+
+use rocket::serde::Serialize;
+
+use crate::lifecycle::Stay;
+
+pub static DEFAULT_WINDOW_DAYS: u32 = 90;
+
+/// Time and kind (`added`, `moved`, `removed`) of a queue change.
+pub type ChangeTime = (DateTime<Utc>, String);
+
+/// Distribution of the time spent in a folder.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Dwell {
+    pub stays: usize,
+    pub median_seconds: i64,
+    pub p90_seconds: i64,
+    pub p99_seconds: i64,
+}
+
+impl Dwell {
+    fn new(mut seconds: Vec<i64>) -> Option<Dwell> {
+        if seconds.is_empty() {
+            return None;
+        }
+        seconds.sort_unstable();
+        Some(Dwell {
+            stays: seconds.len(),
+            median_seconds: percentile(&seconds, 50),
+            p90_seconds: percentile(&seconds, 90),
+            p99_seconds: percentile(&seconds, 99),
+        })
+    }
+}
+
+// Nearest rank, `sorted` must not be empty
+fn percentile(sorted: &[i64], p: usize) -> i64 {
+    let rank = (p * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct WeekdayDwell {
+    pub weekday: Weekday,
+    #[serde(flatten)]
+    pub dwell: Dwell,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct HourDwell {
+    pub hour: u32,
+    #[serde(flatten)]
+    pub dwell: Dwell,
+}
+
+/// Dwell times of one folder, overall and by the weekday and hour (UTC) the stays began.
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FolderStats {
+    pub folder: String,
+    #[serde(flatten)]
+    pub dwell: Dwell,
+    pub by_weekday: Vec<WeekdayDwell>,
+    pub by_hour: Vec<HourDwell>,
+}
+
+/// Submissions entering and leaving the queue on one day (UTC).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DailyThroughput {
+    pub date: NaiveDate,
+    pub arrivals: usize,
+    pub departures: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Stats {
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub folders: Vec<FolderStats>,
+    pub daily: Vec<DailyThroughput>,
+}
+
+/// Aggregates closed folder stays and queue changes.
+pub fn compute(
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    stays: &[Stay],
+    changes: &[ChangeTime],
+) -> Stats {
+    let mut folders: BTreeMap<&str, Vec<&Stay>> = BTreeMap::new();
+    for stay in stays.iter().filter(|stay| stay.left.is_some()) {
+        folders.entry(&stay.folder).or_default().push(stay);
+    }
+
+    let folders = folders
+        .into_iter()
+        .filter_map(|(folder, stays)| {
+            let seconds = |stay: &Stay| {
+                stay.left
+                    .map_or(0, |left| left.signed_duration_since(stay.entered).num_seconds())
+            };
+
+            let mut by_weekday: BTreeMap<u32, Vec<i64>> = BTreeMap::new();
+            let mut by_hour: BTreeMap<u32, Vec<i64>> = BTreeMap::new();
+            for stay in &stays {
+                by_weekday
+                    .entry(stay.entered.weekday().num_days_from_monday())
+                    .or_default()
+                    .push(seconds(stay));
+                by_hour
+                    .entry(stay.entered.hour())
+                    .or_default()
+                    .push(seconds(stay));
+            }
+
+            Some(FolderStats {
+                folder: folder.to_owned(),
+                dwell: Dwell::new(stays.iter().map(|stay| seconds(stay)).collect())?,
+                by_weekday: by_weekday
+                    .into_iter()
+                    .filter_map(|(day, seconds)| {
+                        Some(WeekdayDwell {
+                            weekday: Weekday::try_from(day as u8).ok()?,
+                            dwell: Dwell::new(seconds)?,
+                        })
+                    })
+                    .collect(),
+                by_hour: by_hour
+                    .into_iter()
+                    .filter_map(|(hour, seconds)| {
+                        Some(HourDwell {
+                            hour,
+                            dwell: Dwell::new(seconds)?,
+                        })
+                    })
+                    .collect(),
+            })
+        })
+        .collect();
+
+    let mut daily: BTreeMap<NaiveDate, DailyThroughput> = BTreeMap::new();
+    for (time, kind) in changes {
+        let date = time.date_naive();
+        let day = daily.entry(date).or_insert(DailyThroughput {
+            date,
+            arrivals: 0,
+            departures: 0,
+        });
+        match kind.as_str() {
+            "added" => day.arrivals += 1,
+            "removed" => day.departures += 1,
+            _ => {}
+        }
+    }
+
+    Stats {
+        since,
+        until,
+        folders,
+        daily: daily.into_values().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn stay(folder: &str, day: u32, hour: u32, minutes: i64) -> Stay {
+        let entered = Utc.with_ymd_and_hms(2022, 10, day, hour, 0, 0).unwrap();
+        let left = entered + chrono::Duration::minutes(minutes);
+        Stay {
+            folder: folder.to_owned(),
+            entered,
+            last_seen: left,
+            left: Some(left),
+        }
+    }
+
+    #[test]
+    fn percentile_uses_nearest_rank() {
+        let seconds: Vec<i64> = (1..=100).collect();
+        assert_eq!(percentile(&seconds, 50), 50);
+        assert_eq!(percentile(&seconds, 90), 90);
+        assert_eq!(percentile(&seconds, 99), 99);
+        assert_eq!(percentile(&[7], 99), 7);
+    }
+
+    #[test]
+    fn compute_groups_dwell_times() {
+        // 2022-10-25 was a Tuesday
+        let stays = vec![
+            stay("pretest", 25, 9, 30),
+            stay("pretest", 25, 9, 50),
+            stay("pretest", 26, 14, 40),
+            stay("waiting", 25, 9, 600),
+        ];
+        let time = |day, hour| Utc.with_ymd_and_hms(2022, 10, day, hour, 0, 0).unwrap();
+        let changes = vec![
+            (time(25, 9), "added".to_owned()),
+            (time(25, 10), "moved".to_owned()),
+            (time(25, 11), "removed".to_owned()),
+            (time(26, 14), "added".to_owned()),
+        ];
+
+        let stats = compute(time(1, 0), time(31, 0), &stays, &changes);
+
+        let pretest = &stats.folders[0];
+        assert_eq!(pretest.folder, "pretest");
+        assert_eq!(
+            pretest.dwell,
+            Dwell {
+                stays: 3,
+                median_seconds: 40 * 60,
+                p90_seconds: 50 * 60,
+                p99_seconds: 50 * 60,
+            }
+        );
+        assert_eq!(pretest.by_weekday[0].weekday, Weekday::Tue);
+        assert_eq!(pretest.by_weekday[0].dwell.stays, 2);
+        assert_eq!(pretest.by_weekday[1].weekday, Weekday::Wed);
+        assert_eq!(pretest.by_hour[0].hour, 9);
+        assert_eq!(pretest.by_hour[1].hour, 14);
+        assert_eq!(stats.folders[1].folder, "waiting");
+
+        assert_eq!(
+            stats.daily,
+            vec![
+                DailyThroughput {
+                    date: NaiveDate::from_ymd_opt(2022, 10, 25).unwrap(),
+                    arrivals: 1,
+                    departures: 1,
+                },
+                DailyThroughput {
+                    date: NaiveDate::from_ymd_opt(2022, 10, 26).unwrap(),
+                    arrivals: 1,
+                    departures: 0,
+                },
+            ]
+        );
+    }
+}
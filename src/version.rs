@@ -0,0 +1,136 @@
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use rocket::serde::{Deserialize, Serialize};
+
+/// A package version ordered like R's `package_version`.
+///
+/// Versions are sequences of non-negative integers separated by `.` or `-`, which R treats
+/// alike, so `1.0-1` equals `1.0.1`. Components compare numerically from the left and a
+/// version that is a prefix of another is the older one, e.g. `1.9 < 1.10 < 1.10.0`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PackageVersion(Vec<u64>);
+
+impl FromStr for PackageVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PackageVersion, String> {
+        let components = s
+            .split(['.', '-'])
+            .map(|c| {
+                // `str::parse` would also take a leading `+`
+                if c.bytes().all(|b| b.is_ascii_digit()) {
+                    c.parse().ok()
+                } else {
+                    None
+                }
+            })
+            .collect::<Option<Vec<u64>>>();
+
+        match components {
+            Some(components) if components.len() >= 2 => Ok(PackageVersion(components)),
+            _ => Err(format!("Invalid package version '{}'", s)),
+        }
+    }
+}
+
+/// How a version differs from an earlier one, by the first component that changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum VersionDelta {
+    Major,
+    Minor,
+    /// Any later component, e.g. `1.2` to `1.2.1` or `1.2-3` to `1.2-4`
+    Patch,
+    Same,
+    Downgrade,
+}
+
+impl PackageVersion {
+    /// Whether this version sorts after `other`, `None` if `other` isn't a valid version.
+    pub fn is_newer_than(&self, other: &str) -> Option<bool> {
+        other
+            .parse::<PackageVersion>()
+            .ok()
+            .map(|other| *self > other)
+    }
+
+    pub fn delta(&self, from: &PackageVersion) -> VersionDelta {
+        match self.cmp(from) {
+            Ordering::Less => VersionDelta::Downgrade,
+            Ordering::Equal => VersionDelta::Same,
+            Ordering::Greater => match self.0.iter().zip(&from.0).position(|(a, b)| a != b) {
+                Some(0) => VersionDelta::Major,
+                Some(1) => VersionDelta::Minor,
+                _ => VersionDelta::Patch,
+            },
+        }
+    }
+}
+
+impl fmt::Display for PackageVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let components: Vec<String> = self.0.iter().map(u64::to_string).collect();
+        write!(f, "{}", components.join("."))
+    }
+}
+
+/// Orders version strings the way R does, falling back to comparing the strings when one
+/// of them isn't a valid version. Valid versions sort first.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    match (a.parse::<PackageVersion>(), b.parse::<PackageVersion>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(s: &str) -> PackageVersion {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_accepts_r_versions() {
+        assert_eq!(version("1.0-1"), version("1.0.1"));
+        assert_eq!(version("0.2-10").to_string(), "0.2.10");
+        for invalid in ["1", "1.x", "1..0", "-1.0", "1.0-", "1.+0", ""] {
+            assert!(invalid.parse::<PackageVersion>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn is_newer_than_compares_numerically() {
+        assert_eq!(version("1.10").is_newer_than("1.9.3"), Some(true));
+        assert_eq!(version("1.0-1").is_newer_than("1.0.1"), Some(false));
+        assert_eq!(version("0.9").is_newer_than("1.0"), Some(false));
+        assert_eq!(version("1.0").is_newer_than("[unknown]"), None);
+    }
+
+    #[test]
+    fn delta_names_the_changed_component() {
+        let delta = |to: &str, from: &str| version(to).delta(&version(from));
+        assert_eq!(delta("2.0", "1.9.3"), VersionDelta::Major);
+        assert_eq!(delta("1.10", "1.9.3"), VersionDelta::Minor);
+        assert_eq!(delta("1.9-4", "1.9.3"), VersionDelta::Patch);
+        assert_eq!(delta("1.9.3.1", "1.9.3"), VersionDelta::Patch);
+        assert_eq!(delta("1.9-3", "1.9.3"), VersionDelta::Same);
+        assert_eq!(delta("1.9", "1.9.3"), VersionDelta::Downgrade);
+    }
+
+    #[test]
+    fn versions_sort_like_r() {
+        let mut versions = vec![
+            "1.10", "1.9.1", "1.10.0", "0.99-9", "1.9", "1.0-10", "1.0-2",
+        ];
+        versions.sort_by(|a, b| compare(a, b));
+        assert_eq!(
+            versions,
+            vec!["0.99-9", "1.0-2", "1.0-10", "1.9", "1.9.1", "1.10", "1.10.0"]
+        );
+        assert_eq!(compare("2.0", "[unknown]"), Ordering::Less);
+    }
+}
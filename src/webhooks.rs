@@ -0,0 +1,832 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use sha2::Sha256;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tracing::{debug, error, info_span, warn, Instrument};
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Status,
+    request::{FromRequest, Outcome},
+    serde::{json, Deserialize, Serialize},
+    tokio::{self, net::lookup_host, sync::broadcast::error::RecvError, time::sleep},
+    Orbit, Request, Rocket,
+};
+
+use crate::config::Settings;
+use crate::events::{Events, QueueEvent};
+use crate::history::History;
+use crate::lifecycle::Transition;
+
+static MAX_ATTEMPTS: u32 = 6;
+static RETRY_DELAY_SECONDS: u64 = 10;
+static REQUEST_TIMEOUT_SECONDS: u64 = 10;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewWebhook {
+    pub url: String,
+    pub pkg_name: Option<String>,
+    pub folder: Option<String>,
+    pub secret: String,
+}
+
+impl NewWebhook {
+    /// Unless `allow_private_addresses`, the url's host must only resolve to public addresses.
+    pub async fn validate(&self, allow_private_addresses: bool) -> Result<(), String> {
+        let url = reqwest::Url::parse(&self.url).map_err(|err| format!("Invalid url: {}", err))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("Unsupported url scheme '{}'", url.scheme()));
+        }
+        if self.secret.is_empty() {
+            return Err("secret must not be empty".to_owned());
+        }
+        if allow_private_addresses {
+            return Ok(());
+        }
+
+        let host = url.host_str().ok_or("url has no host")?;
+        let port = url.port_or_known_default().unwrap_or_default();
+        let addrs: Vec<SocketAddr> = lookup_host((host.trim_matches(['[', ']']), port))
+            .await
+            .map_err(|err| format!("Could not resolve '{}': {}", host, err))?
+            .collect();
+        match addrs.iter().find(|addr| !is_public(addr.ip())) {
+            Some(addr) => Err(format!(
+                "'{}' resolves to non-public address {}",
+                host,
+                addr.ip()
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether `ip` is routable on the internet, i.e. not loopback, private, link-local or
+/// otherwise reserved.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Shared address space (carrier-grade NAT)
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local
+        || (first & 0xfe00) == 0xfc00
+        // Link-local and the deprecated site-local
+        || (first & 0xffc0) == 0xfe80
+        || (first & 0xffc0) == 0xfec0
+        // Documentation
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+        // IPv4-compatible, deprecated
+        || ip.segments()[..6] == [0; 6])
+}
+
+/// Resolves hosts to their public addresses only, so a webhook can't reach the internal
+/// network by changing its DNS records after registration.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("'{}' resolves to no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Request guard for the webhook API, requiring `Authorization: Bearer <webhook_token>`.
+/// Fails with 403 Forbidden if no token is configured and 401 Unauthorized otherwise.
+pub struct Authorized;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authorized {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Authorized, ()> {
+        let token = match request.rocket().state::<Arc<Settings>>() {
+            Some(settings) if !settings.webhook_token.is_empty() => &settings.webhook_token,
+            _ => return Outcome::Error((Status::Forbidden, ())),
+        };
+
+        let given = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match given {
+            Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => {
+                Outcome::Success(Authorized)
+            }
+            _ => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub pkg_name: Option<String>,
+    pub folder: Option<String>,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub created: DateTime<Utc>,
+}
+
+impl Webhook {
+    fn matches(&self, event: &QueueEvent) -> bool {
+        let change = match event {
+            QueueEvent::SubmissionChanged { change, .. } => change,
+            _ => return false,
+        };
+
+        let folder_matches = |folder: &String| match &change.transition {
+            Transition::Added { folder: f } | Transition::Removed { folder: f } => f == folder,
+            Transition::Moved { from, to } => from == folder || to == folder,
+        };
+
+        self.pkg_name
+            .as_ref()
+            .is_none_or(|name| *name == change.package.pkg_name)
+            && self.folder.as_ref().is_none_or(folder_matches)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for DeliveryStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DeliveryStatus, String> {
+        match s {
+            "pending" => Ok(DeliveryStatus::Pending),
+            "delivered" => Ok(DeliveryStatus::Delivered),
+            "failed" => Ok(DeliveryStatus::Failed),
+            _ => Err(format!("Unknown delivery status '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Delivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: json::Value,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub response_status: Option<u16>,
+    pub last_error: Option<String>,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+/// A delivery left `pending`, with the attempts already made.
+#[derive(Clone, Debug)]
+pub struct PendingDelivery {
+    pub id: i64,
+    pub webhook: Webhook,
+    pub event: String,
+    pub body: String,
+    pub attempts: u32,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Payload<'a> {
+    event: &'a str,
+    #[serde(flatten)]
+    data: &'a QueueEvent,
+}
+
+/// Hex encoded HMAC-SHA256 of `body`, sent as `X-Cransubs-Signature: sha256=<signature>`.
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// POSTs matching queue events to registered webhooks, retrying failed deliveries with
+/// exponential backoff. Every attempt is recorded in the history database.
+#[derive(Clone)]
+pub struct Dispatcher {
+    history: Arc<History>,
+    events: Events,
+    client: reqwest::Client,
+    retry_delay: Duration,
+    allow_private_addresses: bool,
+}
+
+impl Dispatcher {
+    pub fn new(history: Arc<History>, events: Events, allow_private_addresses: bool) -> Dispatcher {
+        Dispatcher::with_retry_delay(
+            history,
+            events,
+            allow_private_addresses,
+            Duration::from_secs(RETRY_DELAY_SECONDS),
+        )
+    }
+
+    fn with_retry_delay(
+        history: Arc<History>,
+        events: Events,
+        allow_private_addresses: bool,
+        retry_delay: Duration,
+    ) -> Dispatcher {
+        // Redirects could lead to addresses the resolver never saw
+        let mut client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECONDS))
+            .user_agent(concat!("cransubs/", env!("CARGO_PKG_VERSION")))
+            .redirect(reqwest::redirect::Policy::none());
+        if !allow_private_addresses {
+            client = client.dns_resolver(Arc::new(PublicResolver));
+        }
+
+        Dispatcher {
+            history,
+            events,
+            client: client.build().expect("HTTP client configuration is valid"),
+            retry_delay,
+            allow_private_addresses,
+        }
+    }
+
+    /// IP address hosts skip the resolver, so they are checked here.
+    fn check_address(&self, url: &str) -> Result<(), String> {
+        let url = reqwest::Url::parse(url).map_err(|err| format!("Invalid url: {}", err))?;
+        let ip = match url
+            .host_str()
+            .map(|host| host.trim_matches(['[', ']']).parse())
+        {
+            Some(Ok(ip)) => ip,
+            _ => return Ok(()),
+        };
+        if self.allow_private_addresses || is_public(ip) {
+            Ok(())
+        } else {
+            Err(format!("Non-public address {}", ip))
+        }
+    }
+
+    async fn dispatch(&self, event: &QueueEvent) {
+        let webhooks = match self.history.blocking(|history| history.webhooks()).await {
+            Ok(webhooks) => webhooks,
+            Err(err) => {
+                error!(error = %err, "could not load webhooks");
+                return;
+            }
+        };
+
+        let payload = Payload {
+            event: event.name(),
+            data: event,
+        };
+        let body = json::to_string(&payload).expect("events serialize to JSON");
+
+        for webhook in webhooks.into_iter().filter(|w| w.matches(event)) {
+            let (webhook_id, name, stored) = (webhook.id, event.name(), body.clone());
+            let delivery_id = match self
+                .history
+                .blocking(move |history| history.add_delivery(webhook_id, name, &stored))
+                .await
+            {
+                Ok(id) => id,
+                Err(err) => {
+                    error!(webhook_id = webhook.id, error = %err, "could not store delivery");
+                    continue;
+                }
+            };
+            let span = info_span!("delivery", webhook_id = webhook.id, delivery_id);
+            tokio::spawn(
+                self.clone()
+                    .deliver(PendingDelivery {
+                        id: delivery_id,
+                        webhook,
+                        event: event.name().to_owned(),
+                        body: body.clone(),
+                        attempts: 0,
+                    })
+                    .instrument(span),
+            );
+        }
+    }
+
+    /// Retries deliveries left `pending` when the server last stopped.
+    async fn resume(&self) {
+        let pending = match self
+            .history
+            .blocking(|history| history.pending_deliveries())
+            .await
+        {
+            Ok(pending) => pending,
+            Err(err) => {
+                error!(error = %err, "could not load pending deliveries");
+                return;
+            }
+        };
+
+        for delivery in pending {
+            let span = info_span!(
+                "delivery",
+                webhook_id = delivery.webhook.id,
+                delivery_id = delivery.id
+            );
+            tokio::spawn(self.clone().deliver(delivery).instrument(span));
+        }
+    }
+
+    async fn deliver(self, delivery: PendingDelivery) {
+        let PendingDelivery {
+            id: delivery_id,
+            webhook,
+            event,
+            body,
+            attempts,
+        } = delivery;
+        let signature = format!("sha256={}", signature(&webhook.secret, body.as_bytes()));
+
+        let refused = match self.check_address(&webhook.url) {
+            Ok(()) if attempts < MAX_ATTEMPTS => None,
+            Ok(()) => Some("attempts exhausted".to_owned()),
+            Err(err) => Some(err),
+        };
+        if let Some(refused) = refused {
+            warn!(error = %refused, "giving up on webhook delivery");
+            let updated = self
+                .history
+                .blocking(move |history| {
+                    history.update_delivery(
+                        delivery_id,
+                        DeliveryStatus::Failed,
+                        attempts,
+                        None,
+                        Some(&refused),
+                    )
+                })
+                .await;
+            if let Err(err) = updated {
+                error!(error = %err, "could not update delivery");
+            }
+            return;
+        }
+
+        for attempt in attempts + 1..=MAX_ATTEMPTS {
+            let res = self
+                .client
+                .post(&webhook.url)
+                .header("Content-Type", "application/json")
+                .header("X-Cransubs-Event", &event)
+                .header("X-Cransubs-Delivery", delivery_id.to_string())
+                .header("X-Cransubs-Signature", &signature)
+                .body(body.clone())
+                .send()
+                .await;
+
+            let (response_status, error) = match res {
+                Ok(res) if res.status().is_success() => (Some(res.status().as_u16()), None),
+                Ok(res) => (
+                    Some(res.status().as_u16()),
+                    Some(format!("HTTP {}", res.status())),
+                ),
+                Err(err) => (None, Some(err.to_string())),
+            };
+
+            let status = match error {
+                None => DeliveryStatus::Delivered,
+                Some(_) if attempt == MAX_ATTEMPTS => DeliveryStatus::Failed,
+                Some(_) => DeliveryStatus::Pending,
+            };
+
+            let stored_error = error.clone();
+            let updated = self
+                .history
+                .blocking(move |history| {
+                    history.update_delivery(
+                        delivery_id,
+                        status,
+                        attempt,
+                        response_status,
+                        stored_error.as_deref(),
+                    )
+                })
+                .await;
+            if let Err(err) = updated {
+                error!(error = %err, "could not update delivery");
+            }
+
+            match (&error, status) {
+                (None, _) => debug!(attempt, "webhook delivered"),
+                (Some(error), DeliveryStatus::Failed) => {
+                    warn!(attempt, error = %error, "giving up on webhook delivery")
+                }
+                (Some(error), _) => debug!(attempt, error = %error, "webhook delivery failed"),
+            }
+
+            if status != DeliveryStatus::Pending {
+                return;
+            }
+            sleep(self.retry_delay * 2u32.pow(attempt - 1)).await;
+        }
+    }
+
+    async fn run(self) {
+        let mut receiver = self.events.subscribe();
+        self.resume().await;
+        loop {
+            match receiver.recv().await {
+                Ok(event) => self.dispatch(&event).await,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "webhook dispatcher lagged behind, events skipped")
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Dispatcher {
+    fn info(&self) -> Info {
+        Info {
+            name: "Deliver queue events to webhooks",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, _rocket: &Rocket<Orbit>) {
+        tokio::spawn(self.clone().run());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::{Change, PackageKey};
+    use rocket::tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+        time::timeout,
+    };
+    use std::collections::HashMap;
+
+    struct Request {
+        headers: HashMap<String, String>,
+        body: String,
+    }
+
+    async fn read_request(stream: &mut TcpStream) -> Request {
+        let mut reader = BufReader::new(stream);
+        let mut headers = HashMap::new();
+
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        loop {
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+            match line.trim_end().split_once(": ") {
+                Some((name, value)) => headers.insert(name.to_lowercase(), value.to_owned()),
+                None => break,
+            };
+        }
+
+        let mut body = vec![0; headers["content-length"].parse().unwrap()];
+        reader.read_exact(&mut body).await.unwrap();
+
+        Request {
+            headers,
+            body: String::from_utf8(body).unwrap(),
+        }
+    }
+
+    fn added(pkg_name: &str, folder: &str) -> QueueEvent {
+        QueueEvent::SubmissionChanged {
+            time: Utc::now(),
+            change: Change {
+                package: PackageKey {
+                    pkg_name: pkg_name.to_owned(),
+                    pkg_version: "1.0".to_owned(),
+                },
+                transition: Transition::Added {
+                    folder: folder.to_owned(),
+                },
+            },
+        }
+    }
+
+    #[rocket::async_test]
+    async fn dispatch_retries_signed_deliveries() {
+        // Local stand-in receiver that fails the first delivery attempt
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in ["500 Internal Server Error", "204 No Content"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                requests.push(read_request(&mut stream).await);
+                let response = format!("HTTP/1.1 {}\r\nConnection: close\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+
+        let history = Arc::new(History::open(":memory:").unwrap());
+        let webhook = history
+            .add_webhook(&NewWebhook {
+                url,
+                pkg_name: Some("foo".to_owned()),
+                folder: None,
+                secret: "s3cret".to_owned(),
+            })
+            .unwrap();
+
+        let dispatcher = Dispatcher::with_retry_delay(
+            history.clone(),
+            Events::new(),
+            true,
+            Duration::from_millis(10),
+        );
+        dispatcher.dispatch(&added("bar", "pretest")).await;
+        dispatcher.dispatch(&added("foo", "pretest")).await;
+
+        let requests = timeout(Duration::from_secs(10), receiver)
+            .await
+            .unwrap()
+            .unwrap();
+        let request = &requests[1];
+        assert_eq!(request.headers["x-cransubs-event"], "submission_added");
+        assert_eq!(
+            request.headers["x-cransubs-signature"],
+            format!("sha256={}", signature("s3cret", request.body.as_bytes()))
+        );
+        let payload: json::Value = json::from_str(&request.body).unwrap();
+        assert_eq!(payload["pkg_name"], "foo");
+        assert_eq!(payload["folder"], "pretest");
+
+        // The receiver answered before the dispatcher recorded the outcome
+        let mut deliveries = Vec::new();
+        for _ in 0..100 {
+            deliveries = history.deliveries(webhook.id).unwrap().unwrap();
+            if deliveries[0].status != DeliveryStatus::Pending {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].status, DeliveryStatus::Delivered);
+        assert_eq!(deliveries[0].attempts, 2);
+        assert_eq!(deliveries[0].response_status, Some(204));
+    }
+
+    #[rocket::async_test]
+    async fn run_resumes_pending_deliveries() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut stream).await;
+            let response = "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n";
+            stream.write_all(response.as_bytes()).await.unwrap();
+            request
+        });
+
+        // Deliveries interrupted by a restart, one of them out of attempts
+        let history = Arc::new(History::open(":memory:").unwrap());
+        let webhook = history
+            .add_webhook(&NewWebhook {
+                url,
+                pkg_name: None,
+                folder: None,
+                secret: "s3cret".to_owned(),
+            })
+            .unwrap();
+        let body = r#"{"event":"submission_added","pkg_name":"foo"}"#;
+        let exhausted = history
+            .add_delivery(webhook.id, "submission_added", body)
+            .unwrap();
+        history
+            .update_delivery(
+                exhausted,
+                DeliveryStatus::Pending,
+                MAX_ATTEMPTS,
+                Some(500),
+                None,
+            )
+            .unwrap();
+        let interrupted = history
+            .add_delivery(webhook.id, "submission_added", body)
+            .unwrap();
+        history
+            .update_delivery(interrupted, DeliveryStatus::Pending, 2, Some(500), None)
+            .unwrap();
+
+        let dispatcher = Dispatcher::with_retry_delay(
+            history.clone(),
+            Events::new(),
+            true,
+            Duration::from_millis(10),
+        );
+        tokio::spawn(dispatcher.run());
+
+        let request = timeout(Duration::from_secs(10), receiver)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            request.headers["x-cransubs-delivery"],
+            interrupted.to_string()
+        );
+        assert_eq!(request.body, body);
+
+        let mut deliveries = Vec::new();
+        for _ in 0..100 {
+            deliveries = history.deliveries(webhook.id).unwrap().unwrap();
+            if deliveries
+                .iter()
+                .all(|d| d.status != DeliveryStatus::Pending)
+            {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(deliveries[0].id, interrupted);
+        assert_eq!(deliveries[0].status, DeliveryStatus::Delivered);
+        assert_eq!(deliveries[0].attempts, 3);
+        assert_eq!(deliveries[1].id, exhausted);
+        assert_eq!(deliveries[1].status, DeliveryStatus::Failed);
+        assert_eq!(deliveries[1].attempts, MAX_ATTEMPTS);
+    }
+
+    #[test]
+    fn is_public_rejects_internal_addresses() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(ip.parse().unwrap()), "rejected {}", ip);
+        }
+        for ip in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "::ffff:127.0.0.1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "accepted {}", ip);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn validate_rejects_private_addresses() {
+        let webhook = |url: &str| NewWebhook {
+            url: url.to_owned(),
+            pkg_name: None,
+            folder: None,
+            secret: "s3cret".to_owned(),
+        };
+
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://[::1]:8080/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://localhost/hook",
+        ] {
+            assert!(
+                webhook(url).validate(false).await.is_err(),
+                "accepted {}",
+                url
+            );
+            assert!(
+                webhook(url).validate(true).await.is_ok(),
+                "rejected {}",
+                url
+            );
+        }
+        assert!(webhook("http://93.184.216.34/hook")
+            .validate(false)
+            .await
+            .is_ok());
+        assert!(webhook("ftp://93.184.216.34/hook")
+            .validate(true)
+            .await
+            .is_err());
+    }
+
+    #[get("/")]
+    fn guarded(_auth: Authorized) {}
+
+    #[rocket::async_test]
+    async fn authorized_requires_the_configured_token() {
+        use rocket::local::asynchronous::Client;
+
+        let client = |token: &str| {
+            let settings = Settings {
+                webhook_token: token.to_owned(),
+                ..Settings::default()
+            };
+            let rocket = rocket::build()
+                .manage(Arc::new(settings))
+                .mount("/", routes![guarded]);
+            Client::untracked(rocket)
+        };
+
+        let disabled = client("").await.unwrap();
+        let res = disabled
+            .get("/")
+            .header(rocket::http::Header::new("Authorization", "Bearer "))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Forbidden);
+
+        let enabled = client("t0ken").await.unwrap();
+        for header in [
+            None,
+            Some("Bearer t0ke"),
+            Some("Bearer t0ken2"),
+            Some("t0ken"),
+        ] {
+            let mut req = enabled.get("/");
+            if let Some(header) = header {
+                req = req.header(rocket::http::Header::new("Authorization", header));
+            }
+            assert_eq!(req.dispatch().await.status(), Status::Unauthorized);
+        }
+        let res = enabled
+            .get("/")
+            .header(rocket::http::Header::new("Authorization", "Bearer t0ken"))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+    }
+}